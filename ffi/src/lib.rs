@@ -0,0 +1,397 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! C bindings for StarByFace.
+//!
+//! `include/starbyface.h` is generated by cbindgen in the
+//! `OUT_DIR` of the build script when the crate is built.
+//!
+//! Panics are caught before they unwind into C: lookups fail
+//! with `STAR_BY_FACE_ERROR_CODE_ERROR` and other functions
+//! return NULL, 0 or `STAR_BY_FACE_ERROR_CODE_INVALID_ARGUMENT`.
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use starbyface::{Celebrity, Client, Lookup, Source, StarByFaceError};
+
+/// Error code of a lookup.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StarByFaceErrorCode {
+    /// Lookup succeeded.
+    Ok = 0,
+    /// StarByFace rejected the image or the request failed.
+    Error = 1,
+    /// No celebrity was found.
+    Null = 2,
+    /// Upstream answered with a non-2xx status.
+    Status = 3,
+    /// Upstream answered with neither HTML nor JSON.
+    ContentType = 4,
+    /// Upstream answered with a bot challenge page.
+    Challenge = 5,
+    /// Upstream answered with an empty body.
+    EmptyBody = 6,
+    /// Response looks like a results page but no card was parsed.
+    MarkupChanged = 7,
+    /// A NULL pointer or an invalid UTF-8 string was given.
+    InvalidArgument = 8,
+    /// No face was detected in the image.
+    NoFace = 9,
+    /// Several faces were detected in the image.
+    MultipleFaces = 10,
+    /// The image failed the checks of the quality analyzer.
+    LowQuality = 11,
+    /// The image is malformed, or its metadata can't be stripped.
+    InvalidImage = 12
+}
+
+/// Client used to send requests.
+pub struct StarByFaceClient {
+    client: Client
+}
+
+/// Result of a lookup.
+pub struct StarByFaceLookup {
+    code: StarByFaceErrorCode,
+    message: Option<CString>,
+    warning: Option<CString>,
+    celebrities: Vec<StarByFaceCelebrity>,
+    #[allow(dead_code)]
+    strings: Vec<CString>
+}
+
+/// Celebrity of a lookup.
+///
+/// Strings are owned by the lookup and valid until it is freed.
+#[repr(C)]
+pub struct StarByFaceCelebrity {
+    /// Position within its gender section, starting at 1.
+    pub rank: usize,
+    /// Similarity in percent.
+    pub similarity: f64,
+    pub name: *const c_char,
+    pub gender: *const c_char,
+    pub image: *const c_char,
+    pub wiki: *const c_char
+}
+
+impl StarByFaceLookup {
+    fn new(lookup: &Lookup) -> Self {
+        let warning = lookup.warning().and_then(|warning| CString::new(warning).ok());
+        match lookup.celebrities() {
+            Ok(celebrities) => {
+                let mut strings = vec![];
+                let celebrities = celebrities.iter()
+                    .map(|celebrity| celebrity_to_c(celebrity, &mut strings))
+                    .collect();
+                Self {
+                    code: StarByFaceErrorCode::Ok,
+                    message: None,
+                    warning,
+                    celebrities,
+                    strings
+                }
+            },
+            Err(err) => {
+                let mut lookup = Self::error(code(&err), &err.to_string());
+                lookup.warning = warning;
+                lookup
+            }
+        }
+    }
+
+    fn error(code: StarByFaceErrorCode, message: &str) -> Self {
+        Self {
+            code,
+            message: CString::new(message.replace('\0', "")).ok(),
+            warning: None,
+            celebrities: vec![],
+            strings: vec![]
+        }
+    }
+}
+
+fn celebrity_to_c(celebrity: &Celebrity, strings: &mut Vec<CString>) -> StarByFaceCelebrity {
+    let mut string = |value: &str| {
+        let value = CString::new(value.replace('\0', "")).unwrap_or_default();
+        let pointer = value.as_ptr();
+        strings.push(value);
+        pointer
+    };
+    StarByFaceCelebrity {
+        rank: celebrity.rank,
+        similarity: celebrity.similarity,
+        name: string(&celebrity.name),
+        gender: string(&celebrity.gender.to_string()),
+        image: string(&celebrity.image),
+        wiki: string(&celebrity.wiki)
+    }
+}
+
+fn code(error: &StarByFaceError) -> StarByFaceErrorCode {
+    match *error {
+        StarByFaceError::Error(_) => StarByFaceErrorCode::Error,
+        StarByFaceError::Null(_) => StarByFaceErrorCode::Null,
+        StarByFaceError::Status { .. } => StarByFaceErrorCode::Status,
+        StarByFaceError::ContentType { .. } => StarByFaceErrorCode::ContentType,
+        StarByFaceError::Challenge { .. } => StarByFaceErrorCode::Challenge,
+        StarByFaceError::EmptyBody { .. } => StarByFaceErrorCode::EmptyBody,
+        StarByFaceError::MarkupChanged(_) => StarByFaceErrorCode::MarkupChanged,
+        StarByFaceError::NoFace => StarByFaceErrorCode::NoFace,
+        StarByFaceError::MultipleFaces(_) => StarByFaceErrorCode::MultipleFaces,
+        StarByFaceError::LowQuality(_) => StarByFaceErrorCode::LowQuality,
+        StarByFaceError::InvalidImage(_) => StarByFaceErrorCode::InvalidImage,
+        _ => StarByFaceErrorCode::Error
+    }
+}
+
+/// Return the result of `f`, or `fallback` if it panicked.
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(_) => fallback
+    }
+}
+
+/// Return the string, None if NULL or invalid UTF-8.
+///
+/// # Safety
+///
+/// `string` must be NULL or a valid NUL-terminated string.
+unsafe fn to_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
+
+/// Run the lookup, or return an invalid argument error.
+///
+/// # Safety
+///
+/// `client` must be NULL or returned by `starbyface_client_new`.
+unsafe fn lookup(client: *const StarByFaceClient, source: Option<Source>) -> *mut StarByFaceLookup {
+    let lookup = match (client.as_ref(), source) {
+        (Some(client), Some(source)) => match panic::catch_unwind(AssertUnwindSafe(|| StarByFaceLookup::new(&Lookup::new(&source, &client.client)))) {
+            Ok(lookup) => lookup,
+            Err(_) => StarByFaceLookup::error(StarByFaceErrorCode::Error, "Lookup panicked")
+        },
+        _ => StarByFaceLookup::error(StarByFaceErrorCode::InvalidArgument, "Invalid argument")
+    };
+    Box::into_raw(Box::new(lookup))
+}
+
+/// Create a client.
+///
+/// `base_url` Upstream base URL, NULL for https://starbyface.com.
+///
+/// Return NULL if `base_url` isn't valid UTF-8.
+///
+/// # Safety
+///
+/// `base_url` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_client_new(base_url: *const c_char) -> *mut StarByFaceClient {
+    guard(ptr::null_mut(), || {
+        let client = if base_url.is_null() {
+            Client::new()
+        } else {
+            match to_str(base_url) {
+                Some(base_url) => Client::new().with_base_url(base_url),
+                None => return ptr::null_mut()
+            }
+        };
+        Box::into_raw(Box::new(StarByFaceClient { client }))
+    })
+}
+
+/// Free a client.
+///
+/// # Safety
+///
+/// `client` must be NULL or returned by `starbyface_client_new`,
+/// and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_client_free(client: *mut StarByFaceClient) {
+    guard((), || {
+        if !client.is_null() {
+            drop(Box::from_raw(client))
+        }
+    })
+}
+
+/// Look up an image file.
+///
+/// Return a lookup to free with `starbyface_lookup_free`.
+///
+/// # Safety
+///
+/// `client` must be returned by `starbyface_client_new` and
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_path(client: *const StarByFaceClient, path: *const c_char) -> *mut StarByFaceLookup {
+    lookup(client, to_str(path).map(|path| Source::File(PathBuf::from(path))))
+}
+
+/// Look up an image in memory.
+///
+/// `file_name` File name sent with the image, NULL for "image".
+///
+/// Return a lookup to free with `starbyface_lookup_free`.
+///
+/// # Safety
+///
+/// `client` must be returned by `starbyface_client_new`, `data`
+/// must point to `length` readable bytes and `file_name` must
+/// be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_bytes(client: *const StarByFaceClient, data: *const u8, length: usize, file_name: *const c_char) -> *mut StarByFaceLookup {
+    if data.is_null() {
+        return lookup(client, None)
+    }
+    let file_name = if file_name.is_null() {
+        Some("image")
+    } else {
+        to_str(file_name)
+    };
+    let source = guard(None, || file_name.map(|file_name| Source::Bytes {
+        data: std::slice::from_raw_parts(data, length).to_vec(),
+        file_name: file_name.to_string()
+    }));
+    lookup(client, source)
+}
+
+/// Look up an image URI.
+///
+/// Return a lookup to free with `starbyface_lookup_free`.
+///
+/// # Safety
+///
+/// `client` must be returned by `starbyface_client_new` and
+/// `url` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_url(client: *const StarByFaceClient, url: *const c_char) -> *mut StarByFaceLookup {
+    lookup(client, to_str(url).map(|url| Source::Url(url.to_string())))
+}
+
+/// Free a lookup.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or returned by a `starbyface_lookup_*`
+/// function, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_free(lookup: *mut StarByFaceLookup) {
+    guard((), || {
+        if !lookup.is_null() {
+            drop(Box::from_raw(lookup))
+        }
+    })
+}
+
+/// Return the error code of a lookup.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or a lookup not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_error_code(lookup: *const StarByFaceLookup) -> StarByFaceErrorCode {
+    guard(StarByFaceErrorCode::InvalidArgument, || {
+        match lookup.as_ref() {
+            Some(lookup) => lookup.code,
+            None => StarByFaceErrorCode::InvalidArgument
+        }
+    })
+}
+
+/// Return the error message of a lookup, NULL if it succeeded.
+///
+/// The string is valid until the lookup is freed.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or a lookup not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_error_message(lookup: *const StarByFaceLookup) -> *const c_char {
+    guard(ptr::null(), || {
+        match lookup.as_ref().and_then(|lookup| lookup.message.as_ref()) {
+            Some(message) => message.as_ptr(),
+            None => ptr::null()
+        }
+    })
+}
+
+/// Return the warning message of a lookup, NULL if none.
+///
+/// The string is valid until the lookup is freed.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or a lookup not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_warning(lookup: *const StarByFaceLookup) -> *const c_char {
+    guard(ptr::null(), || {
+        match lookup.as_ref().and_then(|lookup| lookup.warning.as_ref()) {
+            Some(warning) => warning.as_ptr(),
+            None => ptr::null()
+        }
+    })
+}
+
+/// Return the number of celebrities of a lookup.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or a lookup not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_count(lookup: *const StarByFaceLookup) -> usize {
+    guard(0, || {
+        match lookup.as_ref() {
+            Some(lookup) => lookup.celebrities.len(),
+            None => 0
+        }
+    })
+}
+
+/// Return the celebrity at `index`, NULL if out of range.
+///
+/// The celebrity is valid until the lookup is freed.
+///
+/// # Safety
+///
+/// `lookup` must be NULL or a lookup not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn starbyface_lookup_get(lookup: *const StarByFaceLookup, index: usize) -> *const StarByFaceCelebrity {
+    guard(ptr::null(), || {
+        match lookup.as_ref().and_then(|lookup| lookup.celebrities.get(index)) {
+            Some(celebrity) => celebrity,
+            None => ptr::null()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{guard, ptr, StarByFaceErrorCode};
+
+    #[test]
+    fn guard_returns_the_fallback_of_a_panic() {
+        assert_eq!(guard(StarByFaceErrorCode::InvalidArgument, || panic!("lookup")), StarByFaceErrorCode::InvalidArgument);
+        assert!(guard(ptr::null::<u8>(), || panic!("lookup")).is_null());
+        assert_eq!(guard(0, || 42), 42);
+    }
+}
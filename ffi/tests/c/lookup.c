@@ -0,0 +1,96 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+/*
+ * Exercises the C bindings against the mock server started
+ * by tests/c_program.rs.
+ *
+ * Usage: lookup BASE_URL IMAGE_FILE
+ */
+#include <stdio.h>
+#include <string.h>
+#include "starbyface.h"
+
+static int failures = 0;
+
+#define CHECK(condition) do { \
+    if (!(condition)) { \
+        fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+        failures++; \
+    } \
+} while (0)
+
+static void check_results(StarByFaceLookup *lookup) {
+    CHECK(starbyface_lookup_error_code(lookup) == STAR_BY_FACE_ERROR_CODE_OK);
+    CHECK(starbyface_lookup_error_message(lookup) == NULL);
+    CHECK(starbyface_lookup_count(lookup) == 3);
+    for (size_t i = 0; i < starbyface_lookup_count(lookup); i++) {
+        const StarByFaceCelebrity *celebrity = starbyface_lookup_get(lookup, i);
+        CHECK(celebrity != NULL);
+        printf("%zu %s %s %.0f%% %s\n", celebrity->rank, celebrity->gender, celebrity->name, celebrity->similarity, celebrity->wiki);
+    }
+    const StarByFaceCelebrity *first = starbyface_lookup_get(lookup, 0);
+    CHECK(first != NULL && strcmp(first->name, "Ann") == 0);
+    CHECK(first != NULL && strcmp(first->gender, "female") == 0);
+    CHECK(first != NULL && first->similarity == 90.0);
+    CHECK(starbyface_lookup_get(lookup, 3) == NULL);
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "Usage: %s BASE_URL IMAGE_FILE\n", argv[0]);
+        return 2;
+    }
+    StarByFaceClient *client = starbyface_client_new(argv[1]);
+    CHECK(client != NULL);
+
+    StarByFaceLookup *lookup = starbyface_lookup_url(client, "http://example.com/face.jpg");
+    check_results(lookup);
+    starbyface_lookup_free(lookup);
+
+    lookup = starbyface_lookup_path(client, argv[2]);
+    check_results(lookup);
+    starbyface_lookup_free(lookup);
+
+    const unsigned char jpeg[] = {0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x06, 'f', 'a', 'c', 'e', 0xFF, 0xD9};
+    lookup = starbyface_lookup_bytes(client, jpeg, sizeof(jpeg), "face.jpg");
+    check_results(lookup);
+    starbyface_lookup_free(lookup);
+
+    lookup = starbyface_lookup_url(client, "http://example.com/error");
+    CHECK(starbyface_lookup_error_code(lookup) == STAR_BY_FACE_ERROR_CODE_STATUS);
+    CHECK(starbyface_lookup_error_message(lookup) != NULL);
+    CHECK(starbyface_lookup_count(lookup) == 0);
+    starbyface_lookup_free(lookup);
+
+    lookup = starbyface_lookup_path(client, "/nonexistent/face.jpg");
+    CHECK(starbyface_lookup_error_code(lookup) == STAR_BY_FACE_ERROR_CODE_ERROR);
+    CHECK(starbyface_lookup_error_message(lookup) != NULL);
+    starbyface_lookup_free(lookup);
+
+    lookup = starbyface_lookup_url(client, NULL);
+    CHECK(starbyface_lookup_error_code(lookup) == STAR_BY_FACE_ERROR_CODE_INVALID_ARGUMENT);
+    starbyface_lookup_free(lookup);
+
+    CHECK(starbyface_lookup_error_code(NULL) == STAR_BY_FACE_ERROR_CODE_INVALID_ARGUMENT);
+    starbyface_lookup_free(NULL);
+    starbyface_client_free(client);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return 1;
+    }
+    return 0;
+}
@@ -0,0 +1,103 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Builds tests/c/lookup.c against the cdylib and runs it
+//! against a mock StarByFace server.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+
+const CARD: &str = r#"<div class="card" similarity="{similarity}">
+    <div class="bar"><div class="fill"></div>
+    </div>
+    </div>
+    <a href="https://en.wikipedia.org/wiki/{name}" target="_blank">
+    <img class="photo" src="/img/{name}.jpg" />
+    <p class="name">{name}</p>"#;
+
+fn card(name: &str, similarity: &str) -> String {
+    CARD.replace("{name}", name).replace("{similarity}", similarity)
+}
+
+fn respond(stream: TcpStream) {
+    let mut reader = BufReader::new(stream);
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    let _ = reader.read_exact(&mut body);
+    let (status, page) = if String::from_utf8_lossy(&body).contains("/error") {
+        ("502 Bad Gateway", "<h1>Bad gateway</h1>".to_string())
+    } else {
+        ("200 OK", format!(r#"<div id="male-celebs-result">{}{}</div><div id="female-celebs-result">{}</div>"#,
+                          card("Bob", "80"), card("Tom", "70"), card("Ann", "90")))
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, page.len(), page);
+    let _ = reader.get_mut().write_all(response.as_bytes());
+}
+
+#[test]
+fn c_program() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || respond(stream));
+        }
+    });
+
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let library_dir = std::env::current_exe().unwrap()
+        .parent().unwrap()
+        .parent().unwrap()
+        .to_path_buf();
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let program = out_dir.join("lookup");
+    let image = out_dir.join("face.jpg");
+    std::fs::write(&image, [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x06, b'f', b'a', b'c', b'e', 0xFF, 0xD9]).unwrap();
+
+    let compiled = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(manifest_dir.join("tests").join("c").join("lookup.c"))
+        .arg("-I").arg(manifest_dir.join("include"))
+        .arg("-L").arg(&library_dir)
+        .arg("-lstarbyface_ffi")
+        .arg("-o").arg(&program)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(compiled.success());
+
+    let output = Command::new(&program)
+        .arg(&base_url)
+        .arg(&image)
+        .env("LD_LIBRARY_PATH", &library_dir)
+        .env("DYLD_LIBRARY_PATH", &library_dir)
+        .output()
+        .unwrap();
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success());
+}
@@ -0,0 +1,277 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Node.js bindings for StarByFace.
+//!
+//! Lookups block on the network, so they run on threads of the
+//! addon rather than on the libuv thread pool, where a few slow
+//! lookups would hold up `fs`, `dns` and `zlib` calls. At most
+//! `setConcurrency` lookups run at once, later ones are queued.
+//!
+//! `index.d.ts` is generated from this file by `napi build`.
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use starbyface::{Lookup, Source, StarByFaceError};
+
+#[doc(hidden)]
+const DEFAULT_CONCURRENCY: usize = 4;
+
+#[doc(hidden)]
+static WORKERS: Mutex<Workers> = Mutex::new(Workers {
+    queue: VecDeque::new(),
+    running: 0,
+    concurrency: DEFAULT_CONCURRENCY
+});
+
+/// Lookups waiting for a worker thread, workers being started
+/// on demand up to the concurrency and exiting when idle.
+#[doc(hidden)]
+struct Workers {
+    queue: VecDeque<Box<dyn FnOnce() + Send>>,
+    running: usize,
+    concurrency: usize
+}
+
+/// Client used to send requests.
+#[napi]
+pub struct Client {
+    client: starbyface::Client
+}
+
+#[napi]
+impl Client {
+
+    /// `baseUrl` Upstream base URL, https://starbyface.com if omitted.
+    #[napi(constructor)]
+    pub fn new(base_url: Option<String>) -> Self {
+        let client = match base_url {
+            Some(base_url) => starbyface::Client::new().with_base_url(&base_url),
+            None => starbyface::Client::new()
+        };
+        Self { client }
+    }
+}
+
+/// Celebrity of a lookup.
+#[napi(object)]
+pub struct Celebrity {
+    /// Position within its gender section, starting at 1.
+    pub rank: u32,
+    /// Similarity in percent.
+    pub similarity: f64,
+    pub name: String,
+    /// `female`, `male` or the name of another section.
+    pub gender: String,
+    pub image: String,
+    pub wiki: String,
+    /// Attributes of the card element.
+    pub attributes: BTreeMap<String, String>,
+    /// Links of the card other than `wiki`.
+    pub links: Vec<String>
+}
+
+/// `code` of the `Error` a lookup promise is rejected with.
+#[napi(string_enum = "snake_case")]
+pub enum StarByFaceErrorCode {
+    /// StarByFace rejected the image or the request failed.
+    Error,
+    /// No celebrity was found.
+    Null,
+    /// Upstream answered with a non-2xx status, set in `status`.
+    Status,
+    /// Upstream answered with neither HTML nor JSON.
+    ContentType,
+    /// Upstream answered with a bot challenge page.
+    Challenge,
+    /// Upstream answered with an empty body.
+    EmptyBody,
+    /// Response looks like a results page but no card was parsed.
+    MarkupChanged,
+    /// No face was detected in the image.
+    NoFace,
+    /// Several faces were detected in the image.
+    MultipleFaces,
+    /// The image failed the checks of the quality analyzer.
+    LowQuality,
+    /// The image is malformed, or its metadata can't be stripped.
+    InvalidImage
+}
+
+impl From<starbyface::Celebrity> for Celebrity {
+    fn from(celebrity: starbyface::Celebrity) -> Self {
+        Self {
+            rank: celebrity.rank as u32,
+            similarity: celebrity.similarity,
+            name: celebrity.name,
+            gender: celebrity.gender.to_string(),
+            image: celebrity.image,
+            wiki: celebrity.wiki,
+            attributes: celebrity.attributes,
+            links: celebrity.links
+        }
+    }
+}
+
+impl From<&StarByFaceError> for StarByFaceErrorCode {
+    fn from(error: &StarByFaceError) -> Self {
+        match *error {
+            StarByFaceError::Null(_) => StarByFaceErrorCode::Null,
+            StarByFaceError::Status { .. } => StarByFaceErrorCode::Status,
+            StarByFaceError::ContentType { .. } => StarByFaceErrorCode::ContentType,
+            StarByFaceError::Challenge { .. } => StarByFaceErrorCode::Challenge,
+            StarByFaceError::EmptyBody { .. } => StarByFaceErrorCode::EmptyBody,
+            StarByFaceError::MarkupChanged(_) => StarByFaceErrorCode::MarkupChanged,
+            StarByFaceError::NoFace => StarByFaceErrorCode::NoFace,
+            StarByFaceError::MultipleFaces(_) => StarByFaceErrorCode::MultipleFaces,
+            StarByFaceError::LowQuality(_) => StarByFaceErrorCode::LowQuality,
+            StarByFaceError::InvalidImage(_) => StarByFaceErrorCode::InvalidImage,
+            _ => StarByFaceErrorCode::Error
+        }
+    }
+}
+
+#[doc(hidden)]
+fn workers() -> MutexGuard<'static, Workers> {
+    match WORKERS.lock() {
+        Ok(workers) => workers,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Queue `job`, starting a worker if fewer than the concurrency run.
+#[doc(hidden)]
+fn spawn(job: Box<dyn FnOnce() + Send>) {
+    let mut workers = workers();
+    workers.queue.push_back(job);
+    start(&mut workers);
+}
+
+/// Start workers for the queued jobs up to the concurrency.
+#[doc(hidden)]
+fn start(workers: &mut Workers) {
+    while workers.running < workers.concurrency && workers.running < workers.queue.len() {
+        workers.running += 1;
+        thread::spawn(work);
+    }
+}
+
+/// Run queued jobs until the queue is empty or there are more
+/// workers than the concurrency.
+#[doc(hidden)]
+fn work() {
+    loop {
+        let job = {
+            let mut workers = workers();
+            match workers.queue.pop_front() {
+                Some(job) if workers.running <= workers.concurrency => job,
+                job => {
+                    if let Some(job) = job {
+                        workers.queue.push_front(job);
+                    }
+                    workers.running -= 1;
+                    return
+                }
+            }
+        };
+        job();
+    }
+}
+
+/// Return a promise of the celebrities of `source`, looked up on
+/// a worker thread.
+#[doc(hidden)]
+fn lookup<'env>(env: &'env Env, source: Source, client: Option<&Client>) -> Result<Object<'env>> {
+    let client = match client {
+        Some(client) => client.client.clone(),
+        None => starbyface::Client::shared()
+    };
+    let (deferred, promise) = env.create_deferred()?;
+    spawn(Box::new(move || {
+        let celebrities = match panic::catch_unwind(AssertUnwindSafe(|| Lookup::new(&source, &client).celebrities())) {
+            Ok(celebrities) => celebrities,
+            Err(_) => Err(StarByFaceError::Error(String::from("Lookup panicked")))
+        };
+        deferred.resolve(move |env| match celebrities {
+            Ok(celebrities) => Ok(celebrities.into_iter().map(Celebrity::from).collect::<Vec<Celebrity>>()),
+            Err(err) => Err(to_js_error(&env, &err)?)
+        });
+    }));
+    Ok(promise)
+}
+
+/// Return an `Error` with `code` set to the kind of the error,
+/// and `status` to the HTTP status if there is one.
+#[doc(hidden)]
+fn to_js_error(env: &Env, error: &StarByFaceError) -> Result<Error> {
+    let mut js_error = env.create_error(Error::from_reason(error.to_string()))?;
+    js_error.set_named_property("code", StarByFaceErrorCode::from(error))?;
+    match *error {
+        StarByFaceError::Status { status, .. } |
+        StarByFaceError::ContentType { status, .. } |
+        StarByFaceError::Challenge { status, .. } |
+        StarByFaceError::EmptyBody { status } => js_error.set_named_property("status", status as u32)?,
+        _ => {}
+    }
+    Ok(Error::from(js_error.to_unknown()))
+}
+
+/// `concurrency` Number of lookups running at once, 4 by default.
+///
+/// Lookups run on threads of the addon and never occupy the
+/// libuv thread pool, whatever `UV_THREADPOOL_SIZE` is.
+#[napi]
+pub fn set_concurrency(concurrency: u32) {
+    let mut workers = workers();
+    workers.concurrency = (concurrency as usize).max(1);
+    start(&mut workers);
+}
+
+/// Look up an image file.
+///
+/// Return promise of celebrities, rejected with an `Error` whose
+/// `code` is a `StarByFaceErrorCode`.
+#[napi(ts_return_type = "Promise<Array<Celebrity>>")]
+pub fn lookup_path<'env>(env: &'env Env, path: String, client: Option<&Client>) -> Result<Object<'env>> {
+    lookup(env, Source::File(PathBuf::from(path)), client)
+}
+
+/// Look up an image in memory.
+///
+/// `fileName` File name sent with the image, `image` if omitted.
+///
+/// Return promise of celebrities, rejected with an `Error` whose
+/// `code` is a `StarByFaceErrorCode`.
+#[napi(ts_return_type = "Promise<Array<Celebrity>>")]
+pub fn lookup_bytes<'env>(env: &'env Env, data: Buffer, file_name: Option<String>, client: Option<&Client>) -> Result<Object<'env>> {
+    let source = Source::Bytes {
+        data: data.to_vec(),
+        file_name: file_name.unwrap_or_else(|| String::from("image"))
+    };
+    lookup(env, source, client)
+}
+
+/// Look up an image URI.
+///
+/// Return promise of celebrities, rejected with an `Error` whose
+/// `code` is a `StarByFaceErrorCode`.
+#[napi(ts_return_type = "Promise<Array<Celebrity>>")]
+pub fn lookup_url<'env>(env: &'env Env, url: String, client: Option<&Client>) -> Result<Object<'env>> {
+    lookup(env, Source::Url(url), client)
+}
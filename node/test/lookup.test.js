@@ -0,0 +1,101 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+const assert = require('node:assert')
+const { readFile } = require('node:fs/promises')
+const http = require('node:http')
+const { test } = require('node:test')
+const starbyface = require('..')
+
+const CARD = `<div class="card" similarity="90">
+    <div class="bar"><div class="fill"></div></div></div>
+    <a href="https://en.wikipedia.org/wiki/Ann" target="_blank">
+    <img class="photo" src="/img/Ann.jpg" />
+    <p class="name">Ann</p>`
+
+const RESULTS = `<div id="female-celebs-result">${CARD}</div>`
+
+/** Start a server answering every request after `delay` milliseconds and return a client of it. */
+async function serve (status, body, delay = 0) {
+  const server = http.createServer((request, response) => {
+    request.resume()
+    request.on('end', () => setTimeout(() => {
+      response.writeHead(status, { 'Content-Type': 'text/html' })
+      response.end(body)
+    }, delay))
+  })
+  await new Promise((resolve) => server.listen(0, '127.0.0.1', resolve))
+  const client = new starbyface.Client(`http://127.0.0.1:${server.address().port}`)
+  return { client, close: () => server.close() }
+}
+
+test('looks up an image URL', async () => {
+  const { client, close } = await serve(200, RESULTS)
+  try {
+    const celebrities = await starbyface.lookupUrl('https://example.com/face.jpg', client)
+    assert.deepStrictEqual(celebrities.map((celebrity) => celebrity.name), ['Ann'])
+    assert.strictEqual(celebrities[0].similarity, 90)
+    assert.strictEqual(celebrities[0].wiki, 'https://en.wikipedia.org/wiki/Ann')
+  } finally {
+    close()
+  }
+})
+
+test('rejects with the code and status of the error', async () => {
+  const { client, close } = await serve(502, '<h1>Bad gateway</h1>')
+  try {
+    await assert.rejects(starbyface.lookupBytes(Buffer.from([0xff, 0xd8, 0xff, 0xd9]), 'face.jpg', client), (err) => {
+      assert.ok(err instanceof Error)
+      assert.strictEqual(err.code, starbyface.StarByFaceErrorCode.Status)
+      assert.strictEqual(err.status, 502)
+      return true
+    })
+  } finally {
+    close()
+  }
+})
+
+test('rejects missing files', async () => {
+  await assert.rejects(starbyface.lookupPath('/nonexistent/face.jpg'), { code: 'error' })
+})
+
+test('does not hold up the libuv thread pool', async () => {
+  const { client, close } = await serve(200, RESULTS, 500)
+  try {
+    starbyface.setConcurrency(8)
+    const lookups = Array.from({ length: 8 }, () => starbyface.lookupUrl('https://example.com/face.jpg', client))
+    const start = Date.now()
+    await readFile(__filename)
+    assert.ok(Date.now() - start < 400, 'fs waited for the lookups')
+    assert.strictEqual((await Promise.all(lookups)).length, 8)
+  } finally {
+    starbyface.setConcurrency(4)
+    close()
+  }
+})
+
+test('queues lookups over the concurrency', async () => {
+  const { client, close } = await serve(200, RESULTS, 100)
+  try {
+    starbyface.setConcurrency(1)
+    const start = Date.now()
+    const lookups = await Promise.all([1, 2, 3].map(() => starbyface.lookupUrl('https://example.com/face.jpg', client)))
+    assert.strictEqual(lookups.length, 3)
+    assert.ok(Date.now() - start >= 300, 'lookups ran at once')
+  } finally {
+    starbyface.setConcurrency(4)
+    close()
+  }
+})
@@ -0,0 +1,189 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Python bindings for StarByFace.
+//!
+//! Built as the `starbyface` module with maturin.
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use starbyface::{Lookup, Source, StarByFaceError};
+
+create_exception!(starbyface, Error, PyException, "Base class of StarByFace errors.");
+create_exception!(starbyface, RequestError, Error, "StarByFace rejected the image or the request failed.");
+create_exception!(starbyface, NoResultError, Error, "No celebrity was found.");
+create_exception!(starbyface, StatusError, Error, "Upstream answered with a non-2xx status.");
+create_exception!(starbyface, ContentTypeError, Error, "Upstream answered with neither HTML nor JSON.");
+create_exception!(starbyface, ChallengeError, Error, "Upstream answered with a bot challenge page.");
+create_exception!(starbyface, EmptyBodyError, Error, "Upstream answered with an empty body.");
+create_exception!(starbyface, MarkupChangedError, Error, "Response looks like a results page but no card was parsed.");
+create_exception!(starbyface, NoFaceError, Error, "No face was detected in the image.");
+create_exception!(starbyface, MultipleFacesError, Error, "Several faces were detected in the image.");
+create_exception!(starbyface, LowQualityError, Error, "Quality of the image was below the thresholds.");
+create_exception!(starbyface, InvalidImageError, Error, "Image is malformed or its metadata can't be stripped.");
+
+/// Client used to send requests.
+#[pyclass(frozen, module = "starbyface")]
+struct Client {
+    client: starbyface::Client
+}
+
+#[pymethods]
+impl Client {
+
+    /// `base_url` Upstream base URL, None for https://starbyface.com.
+    #[new]
+    #[pyo3(signature = (base_url=None))]
+    fn new(base_url: Option<&str>) -> Self {
+        let client = match base_url {
+            Some(base_url) => starbyface::Client::new().with_base_url(base_url),
+            None => starbyface::Client::new()
+        };
+        Self { client }
+    }
+}
+
+/// Celebrity of a lookup.
+#[pyclass(frozen, get_all, eq, module = "starbyface")]
+#[derive(Clone, PartialEq)]
+struct Celebrity {
+    /// Position within its gender section, starting at 1.
+    rank: usize,
+    /// Similarity in percent.
+    similarity: f64,
+    name: String,
+    /// `female`, `male` or the name of another section.
+    gender: String,
+    image: String,
+    wiki: String,
+    /// Attributes of the card element.
+    attributes: BTreeMap<String, String>,
+    /// Links of the card other than `wiki`.
+    links: Vec<String>
+}
+
+#[pymethods]
+impl Celebrity {
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("Celebrity(rank={}, similarity={}, name={}, gender={}, image={}, wiki={}, attributes={}, links={})",
+                   self.rank, self.similarity.into_pyobject(py)?.repr()?, self.name.as_str().into_pyobject(py)?.repr()?,
+                   self.gender.as_str().into_pyobject(py)?.repr()?, self.image.as_str().into_pyobject(py)?.repr()?,
+                   self.wiki.as_str().into_pyobject(py)?.repr()?, (&self.attributes).into_pyobject(py)?.repr()?,
+                   (&self.links).into_pyobject(py)?.repr()?))
+    }
+}
+
+impl From<starbyface::Celebrity> for Celebrity {
+    fn from(celebrity: starbyface::Celebrity) -> Self {
+        Self {
+            rank: celebrity.rank,
+            similarity: celebrity.similarity,
+            name: celebrity.name,
+            gender: celebrity.gender.to_string(),
+            image: celebrity.image,
+            wiki: celebrity.wiki,
+            attributes: celebrity.attributes,
+            links: celebrity.links
+        }
+    }
+}
+
+#[doc(hidden)]
+fn to_py_err(error: StarByFaceError) -> PyErr {
+    let message = error.to_string();
+    match error {
+        StarByFaceError::Error(_) => RequestError::new_err(message),
+        StarByFaceError::Null(_) => NoResultError::new_err(message),
+        StarByFaceError::Status { .. } => StatusError::new_err(message),
+        StarByFaceError::ContentType { .. } => ContentTypeError::new_err(message),
+        StarByFaceError::Challenge { .. } => ChallengeError::new_err(message),
+        StarByFaceError::EmptyBody { .. } => EmptyBodyError::new_err(message),
+        StarByFaceError::MarkupChanged(_) => MarkupChangedError::new_err(message),
+        StarByFaceError::NoFace => NoFaceError::new_err(message),
+        StarByFaceError::MultipleFaces(_) => MultipleFacesError::new_err(message),
+        StarByFaceError::LowQuality(_) => LowQualityError::new_err(message),
+        StarByFaceError::InvalidImage(_) => InvalidImageError::new_err(message),
+        _ => Error::new_err(message)
+    }
+}
+
+/// Run the lookup without holding the GIL.
+#[doc(hidden)]
+fn lookup(py: Python<'_>, source: Source, client: Option<&Client>) -> PyResult<Vec<Celebrity>> {
+    let client = match client {
+        Some(client) => client.client.clone(),
+        None => starbyface::Client::shared()
+    };
+    let celebrities = py.detach(|| Lookup::new(&source, &client).celebrities());
+    match celebrities {
+        Ok(celebrities) => Ok(celebrities.into_iter().map(Celebrity::from).collect()),
+        Err(err) => Err(to_py_err(err))
+    }
+}
+
+/// Look up an image file.
+///
+/// Return list of celebrities.
+#[pyfunction]
+#[pyo3(signature = (path, client=None))]
+fn lookup_path(py: Python<'_>, path: PathBuf, client: Option<&Client>) -> PyResult<Vec<Celebrity>> {
+    lookup(py, Source::File(path), client)
+}
+
+/// Look up an image in memory.
+///
+/// `file_name` File name sent with the image.
+///
+/// Return list of celebrities.
+#[pyfunction]
+#[pyo3(signature = (data, file_name="image", client=None))]
+fn lookup_bytes(py: Python<'_>, data: &[u8], file_name: &str, client: Option<&Client>) -> PyResult<Vec<Celebrity>> {
+    lookup(py, Source::Bytes { data: data.to_vec(), file_name: file_name.to_string() }, client)
+}
+
+/// Look up an image URI.
+///
+/// Return list of celebrities.
+#[pyfunction]
+#[pyo3(signature = (url, client=None))]
+fn lookup_url(py: Python<'_>, url: &str, client: Option<&Client>) -> PyResult<Vec<Celebrity>> {
+    lookup(py, Source::Url(url.to_string()), client)
+}
+
+#[pymodule]
+#[pyo3(name = "starbyface")]
+fn starbyface_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Client>()?;
+    m.add_class::<Celebrity>()?;
+    m.add_function(wrap_pyfunction!(lookup_path, m)?)?;
+    m.add_function(wrap_pyfunction!(lookup_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(lookup_url, m)?)?;
+    let py = m.py();
+    m.add("Error", py.get_type::<Error>())?;
+    m.add("RequestError", py.get_type::<RequestError>())?;
+    m.add("NoResultError", py.get_type::<NoResultError>())?;
+    m.add("StatusError", py.get_type::<StatusError>())?;
+    m.add("ContentTypeError", py.get_type::<ContentTypeError>())?;
+    m.add("ChallengeError", py.get_type::<ChallengeError>())?;
+    m.add("EmptyBodyError", py.get_type::<EmptyBodyError>())?;
+    m.add("MarkupChangedError", py.get_type::<MarkupChangedError>())?;
+    m.add("NoFaceError", py.get_type::<NoFaceError>())?;
+    m.add("MultipleFacesError", py.get_type::<MultipleFacesError>())?;
+    m.add("LowQualityError", py.get_type::<LowQualityError>())?;
+    m.add("InvalidImageError", py.get_type::<InvalidImageError>())?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+# Copyright 2022 XXIV
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+"""Smoke tests of the starbyface module against a local server.
+
+Run with `maturin develop && pytest` from the python directory.
+"""
+import contextlib
+import http.server
+import threading
+
+import pytest
+
+import starbyface
+
+CARD = """<div class="card" similarity="{similarity}">
+    <div class="bar"><div class="fill"></div></div></div>
+    <a href="https://en.wikipedia.org/wiki/{name}" target="_blank">
+    <img class="photo" src="/img/{name}.jpg" />
+    <p class="name">{name}</p>"""
+
+RESULTS = '<div id="female-celebs-result">{}</div>'.format(CARD.format(name="Ann", similarity="90"))
+
+ERRORS = [
+    starbyface.RequestError,
+    starbyface.NoResultError,
+    starbyface.StatusError,
+    starbyface.ContentTypeError,
+    starbyface.ChallengeError,
+    starbyface.EmptyBodyError,
+    starbyface.MarkupChangedError,
+    starbyface.NoFaceError,
+    starbyface.MultipleFacesError,
+    starbyface.LowQualityError,
+    starbyface.InvalidImageError,
+]
+
+
+@contextlib.contextmanager
+def serve(status, body, content_type="text/html", headers=None):
+    """Yield a client of a server answering every request with `body`."""
+
+    class Handler(http.server.BaseHTTPRequestHandler):
+        def do_POST(self):
+            self.rfile.read(int(self.headers.get("Content-Length", 0)))
+            data = body.encode()
+            self.send_response(status)
+            self.send_header("Content-Type", content_type)
+            self.send_header("Content-Length", str(len(data)))
+            for name, value in (headers or {}).items():
+                self.send_header(name, value)
+            self.end_headers()
+            self.wfile.write(data)
+
+        def log_message(self, *args):
+            pass
+
+    server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), Handler)
+    thread = threading.Thread(target=server.serve_forever, daemon=True)
+    thread.start()
+    try:
+        yield starbyface.Client("http://127.0.0.1:{}".format(server.server_address[1]))
+    finally:
+        server.shutdown()
+        server.server_close()
+
+
+@pytest.mark.parametrize("error", ERRORS)
+def test_errors_derive_from_error(error):
+    assert issubclass(error, starbyface.Error)
+    assert issubclass(starbyface.Error, Exception)
+    assert error is not starbyface.Error
+
+
+def test_errors_are_distinct():
+    assert len(set(ERRORS)) == len(ERRORS)
+    for error in ERRORS:
+        for other in ERRORS:
+            assert error is other or not issubclass(error, other)
+
+
+def test_lookup_url():
+    with serve(200, RESULTS) as client:
+        celebrities = starbyface.lookup_url("https://example.com/face.jpg", client)
+    assert [celebrity.name for celebrity in celebrities] == ["Ann"]
+    assert celebrities[0].similarity == 90.0
+    assert celebrities[0].wiki == "https://en.wikipedia.org/wiki/Ann"
+
+
+@pytest.mark.parametrize("status, body, content_type, headers, error", [
+    (502, "<h1>Bad gateway</h1>", "text/html", None, starbyface.StatusError),
+    (200, "%PDF", "application/pdf", None, starbyface.ContentTypeError),
+    (403, "Just a moment...", "text/html", {"cf-mitigated": "challenge"}, starbyface.ChallengeError),
+    (200, "", "text/html", None, starbyface.EmptyBodyError),
+])
+def test_lookup_errors(status, body, content_type, headers, error):
+    with serve(status, body, content_type, headers) as client:
+        with pytest.raises(error) as raised:
+            starbyface.lookup_url("https://example.com/face.jpg", client)
+    assert isinstance(raised.value, starbyface.Error)
+
+
+def test_lookup_missing_file():
+    with pytest.raises(starbyface.RequestError):
+        starbyface.lookup_path("/nonexistent/face.jpg")
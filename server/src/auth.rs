@@ -0,0 +1,315 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use json::JsonValue;
+use starbyface::RateLimiter;
+use crate::routes::error_response;
+use crate::state::AppState;
+
+#[doc(hidden)]
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// API keys loaded from a JSON file:
+///
+/// ```json
+/// {"keys": [{"key": "...", "name": "app", "rate": 2.0, "burst": 5, "daily_quota": 1000}]}
+/// ```
+///
+/// `rate`, `burst` and `daily_quota` are optional. Usage counters
+/// are kept by name in a JSON file and survive restarts.
+#[derive(Clone, Default)]
+pub struct Keys {
+    #[doc(hidden)]
+    keys: HashMap<String, Arc<ApiKey>>,
+    #[doc(hidden)]
+    usage_path: PathBuf
+}
+
+/// API key of a request.
+pub struct ApiKey {
+    pub name: String,
+    #[doc(hidden)]
+    rate_limiter: Option<RateLimiter>,
+    #[doc(hidden)]
+    daily_quota: Option<u64>,
+    #[doc(hidden)]
+    usage: Mutex<Usage>,
+    /// Usage changed since it was last persisted.
+    #[doc(hidden)]
+    changed: AtomicBool
+}
+
+#[doc(hidden)]
+#[derive(Default)]
+struct Usage {
+    day: u64,
+    today: u64,
+    requests: u64,
+    images: u64,
+    rate_limited: u64,
+    quota_exceeded: u64
+}
+
+impl Keys {
+
+    /// `path` JSON file of API keys.
+    ///
+    /// `usage_path` JSON file usage counters are read from and
+    /// persisted to.
+    ///
+    /// Return an error if the file has no keys, or a key or
+    /// name is used twice.
+    pub fn load(path: &Path, usage_path: PathBuf) -> Result<Self, String> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) => return Err(format!("{}: {}", path.display(), err))
+        };
+        let json = match json::parse(&data) {
+            Ok(json) => json,
+            Err(err) => return Err(format!("{}: {}", path.display(), err))
+        };
+        if !json["keys"].is_array() || json["keys"].is_empty() {
+            return Err(format!("{}: \"keys\" must be a non-empty array", path.display()))
+        }
+        let saved = std::fs::read_to_string(&usage_path).ok()
+            .and_then(|data| json::parse(&data).ok())
+            .unwrap_or(JsonValue::Null);
+        let mut keys: HashMap<String, Arc<ApiKey>> = HashMap::new();
+        for (index, entry) in json["keys"].members().enumerate() {
+            let key = match entry["key"].as_str() {
+                Some(key) if !key.is_empty() => key.to_string(),
+                _ => return Err(format!("{}: every entry needs a \"key\"", path.display()))
+            };
+            let name = match entry["name"].as_str() {
+                Some(name) => name.to_string(),
+                None => format!("key-{}", index + 1)
+            };
+            if keys.contains_key(&key) {
+                return Err(format!("{}: key {} is a duplicate", path.display(), index + 1))
+            }
+            if keys.values().any(|api_key| api_key.name == name) {
+                return Err(format!("{}: name \"{}\" is used twice", path.display(), name))
+            }
+            let rate_limiter = match entry["rate"].as_f64() {
+                Some(rate) => match RateLimiter::new(rate, entry["burst"].as_u32().unwrap_or(1)) {
+                    Ok(rate_limiter) => Some(rate_limiter),
+                    Err(err) => return Err(format!("{}: key {}: {}", path.display(), index + 1, err))
+                },
+                None => None
+            };
+            keys.insert(key, Arc::new(ApiKey {
+                usage: Mutex::new(Usage::from_json(&saved[name.as_str()])),
+                name,
+                rate_limiter,
+                daily_quota: entry["daily_quota"].as_u64(),
+                changed: AtomicBool::new(false)
+            }));
+        }
+        Ok(Self {
+            keys,
+            usage_path
+        })
+    }
+
+    /// Write the usage counters of every key if any changed.
+    pub fn persist(&self) {
+        let mut changed = false;
+        for key in self.keys.values() {
+            changed |= key.changed.swap(false, Ordering::SeqCst);
+        }
+        if !changed {
+            return
+        }
+        let mut json = JsonValue::new_object();
+        for key in self.keys.values() {
+            json[key.name.as_str()] = match key.usage.lock() {
+                Ok(usage) => usage.to_json(),
+                Err(poisoned) => poisoned.into_inner().to_json()
+            };
+        }
+        let temporary = self.usage_path.with_extension("json.tmp");
+        let result = std::fs::create_dir_all(self.usage_path.parent().unwrap_or(Path::new(".")))
+            .and_then(|_| std::fs::write(&temporary, json.dump()))
+            .and_then(|_| std::fs::rename(&temporary, &self.usage_path));
+        if let Err(err) = result {
+            tracing::error!(path = %self.usage_path.display(), error = %err, "failed to persist usage");
+        }
+    }
+
+    #[doc(hidden)]
+    fn get(&self, key: &str) -> Option<Arc<ApiKey>> {
+        self.keys.get(key).cloned()
+    }
+}
+
+impl ApiKey {
+
+    /// `images` Number of images about to be looked up.
+    ///
+    /// Return an error if the daily quota would be exceeded.
+    pub fn charge(&self, images: u64) -> Result<(), QuotaExceeded> {
+        let mut usage = self.usage();
+        usage.roll();
+        if let Some(quota) = self.daily_quota {
+            if usage.today + images > quota {
+                usage.quota_exceeded += 1;
+                return Err(QuotaExceeded {
+                    quota,
+                    used: usage.today
+                })
+            }
+        }
+        usage.today += images;
+        usage.images += images;
+        Ok(())
+    }
+
+    /// Return usage counters as a JSON object.
+    pub fn usage_json(&self) -> JsonValue {
+        let mut usage = self.lock_usage();
+        usage.roll();
+        let mut json = JsonValue::new_object();
+        json["name"] = self.name.as_str().into();
+        json["requests"] = usage.requests.into();
+        json["images"] = usage.images.into();
+        json["images_today"] = usage.today.into();
+        json["daily_quota"] = self.daily_quota.into();
+        json["rate_limited"] = usage.rate_limited.into();
+        json["quota_exceeded"] = usage.quota_exceeded.into();
+        json
+    }
+
+    /// Return the usage counters, marked as changed.
+    #[doc(hidden)]
+    fn usage(&self) -> MutexGuard<'_, Usage> {
+        self.changed.store(true, Ordering::SeqCst);
+        self.lock_usage()
+    }
+
+    /// Return the usage counters to read them.
+    #[doc(hidden)]
+    fn lock_usage(&self) -> MutexGuard<'_, Usage> {
+        match self.usage.lock() {
+            Ok(usage) => usage,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+}
+
+/// Daily quota of an API key exceeded.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub quota: u64,
+    pub used: u64
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let message = format!("Daily quota of {} images exceeded, {} used today", self.quota, self.used);
+        let retry = SECONDS_PER_DAY - now() % SECONDS_PER_DAY;
+        with_retry_after(error_response(429, "quota_exceeded", &message), retry)
+    }
+}
+
+impl Usage {
+    #[doc(hidden)]
+    fn from_json(json: &JsonValue) -> Self {
+        Self {
+            day: json["day"].as_u64().unwrap_or_default(),
+            today: json["today"].as_u64().unwrap_or_default(),
+            requests: json["requests"].as_u64().unwrap_or_default(),
+            images: json["images"].as_u64().unwrap_or_default(),
+            rate_limited: json["rate_limited"].as_u64().unwrap_or_default(),
+            quota_exceeded: json["quota_exceeded"].as_u64().unwrap_or_default()
+        }
+    }
+
+    #[doc(hidden)]
+    fn to_json(&self) -> JsonValue {
+        json::object!{
+            "day": self.day,
+            "today": self.today,
+            "requests": self.requests,
+            "images": self.images,
+            "rate_limited": self.rate_limited,
+            "quota_exceeded": self.quota_exceeded
+        }
+    }
+
+    #[doc(hidden)]
+    fn roll(&mut self) {
+        let day = now() / SECONDS_PER_DAY;
+        if day != self.day {
+            self.day = day;
+            self.today = 0;
+        }
+    }
+}
+
+/// Reject requests without a known API key, or over the
+/// rate limit of their key, when keys are configured.
+///
+/// The key is read from `Authorization: Bearer ...` or `X-API-Key`.
+pub async fn authenticate(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let keys = match state.keys {
+        Some(ref keys) => keys,
+        None => return next.run(request).await
+    };
+    let presented = request.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.headers().get("x-api-key").and_then(|value| value.to_str().ok()))
+        .map(|value| value.trim().to_string());
+    let key = match presented.as_deref().and_then(|key| keys.get(key)) {
+        Some(key) => key,
+        None => return error_response(401, "unauthorized", "Missing or invalid API key")
+    };
+    key.usage().requests += 1;
+    if let Some(ref rate_limiter) = key.rate_limiter {
+        match rate_limiter.try_acquire() {
+            Ok(None) => {},
+            Ok(Some(wait)) => {
+                key.usage().rate_limited += 1;
+                let message = format!("Rate limit exceeded, retry in {:.1}s", wait.as_secs_f64());
+                return with_retry_after(error_response(429, "rate_limited", &message), wait.as_secs().max(1))
+            },
+            Err(err) => return error_response(500, "internal", &err.to_string())
+        }
+    }
+    request.extensions_mut().insert(key);
+    next.run(request).await
+}
+
+#[doc(hidden)]
+fn with_retry_after(mut response: Response, seconds: u64) -> Response {
+    if let Ok(value) = seconds.to_string().parse() {
+        response.headers_mut().insert(RETRY_AFTER, value);
+    }
+    response
+}
+
+#[doc(hidden)]
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
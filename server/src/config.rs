@@ -0,0 +1,123 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::env;
+use std::path::PathBuf;
+
+#[doc(hidden)]
+const USAGE: &str = "Usage: starbyface-server [--bind ADDRESS] [--upstream URL] [--jobs-dir DIR] [--concurrency N] [--keys FILE]
+                         [--max-body BYTES] [--max-job-body BYTES] [--max-jobs N] [--job-ttl SECONDS] [--retries N]
+
+Options:
+    --bind ADDRESS     Address to listen on (env STARBYFACE_BIND, default 127.0.0.1:8080)
+    --upstream URL     StarByFace base URL (env STARBYFACE_UPSTREAM, default https://starbyface.com)
+    --jobs-dir DIR     Directory job state is persisted to (env STARBYFACE_JOBS_DIR)
+    --concurrency N    Number of lookups a job runs at once (default 4)
+    --keys FILE        JSON file of API keys (env STARBYFACE_KEYS), no authentication if unset
+    --max-body BYTES   Largest request body (env STARBYFACE_MAX_BODY, default 20971520)
+    --max-job-body BYTES
+                       Largest body of POST /jobs (default 209715200)
+    --max-jobs N       Number of jobs running at once, later ones are queued (default 2)
+    --job-ttl SECONDS  Time finished jobs stay in memory before being read from --jobs-dir (default 600)
+    --retries N        Times a failed upstream request is sent again (default 2)
+    --help             Print this message";
+
+/// Server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    pub upstream: String,
+    pub jobs_dir: PathBuf,
+    pub concurrency: usize,
+    pub keys: Option<PathBuf>,
+    /// Number of lookups kept for `GET /lookup/{id}`.
+    pub max_stored: usize,
+    /// Largest request body in bytes.
+    pub max_body: usize,
+    /// Largest body of `POST /jobs` in bytes.
+    pub max_job_body: usize,
+    /// Number of jobs running at once.
+    pub max_jobs: usize,
+    /// Seconds finished jobs stay in memory.
+    pub job_ttl: u64,
+    /// Times a failed upstream request is sent again.
+    pub retries: u32
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: env::var("STARBYFACE_BIND").unwrap_or_else(|_| "127.0.0.1:8080".to_string()),
+            upstream: env::var("STARBYFACE_UPSTREAM").unwrap_or_else(|_| "https://starbyface.com".to_string()),
+            jobs_dir: env::var_os("STARBYFACE_JOBS_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| env::temp_dir().join("starbyface-jobs")),
+            concurrency: 4,
+            keys: env::var_os("STARBYFACE_KEYS").map(PathBuf::from),
+            max_stored: 1024,
+            max_body: env::var("STARBYFACE_MAX_BODY").ok()
+                .and_then(|max_body| max_body.parse().ok())
+                .unwrap_or(20 * 1024 * 1024),
+            max_job_body: 200 * 1024 * 1024,
+            max_jobs: 2,
+            job_ttl: 600,
+            retries: 2
+        }
+    }
+}
+
+impl Config {
+
+    /// `args` Command line arguments without the program name.
+    ///
+    /// Return usage on `--help` or invalid arguments.
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => config.bind = value(&arg, args.next())?,
+                "--upstream" => config.upstream = value(&arg, args.next())?,
+                "--jobs-dir" => config.jobs_dir = PathBuf::from(value(&arg, args.next())?),
+                "--keys" => config.keys = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--concurrency" => config.concurrency = number(&arg, args.next())?,
+                "--max-body" => config.max_body = number(&arg, args.next())?,
+                "--max-job-body" => config.max_job_body = number(&arg, args.next())?,
+                "--max-jobs" => config.max_jobs = number(&arg, args.next())?,
+                "--job-ttl" => config.job_ttl = number(&arg, args.next())?,
+                "--retries" => config.retries = number(&arg, args.next())?,
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                _ => return Err(format!("Unknown argument: {}\n\n{}", arg, USAGE))
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[doc(hidden)]
+fn value(arg: &str, value: Option<String>) -> Result<String, String> {
+    match value {
+        Some(value) => Ok(value),
+        None => Err(format!("Missing value for {}\n\n{}", arg, USAGE))
+    }
+}
+
+#[doc(hidden)]
+fn number<T: std::str::FromStr>(arg: &str, value: Option<String>) -> Result<T, String> {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(number)) => Ok(number),
+        Some(Err(_)) => Err(format!("Invalid value for {}\n\n{}", arg, USAGE)),
+        None => Err(format!("Missing value for {}\n\n{}", arg, USAGE))
+    }
+}
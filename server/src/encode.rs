@@ -0,0 +1,70 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use json::JsonValue;
+use starbyface::{Celebrity, Lookup, StarByFaceError};
+
+/// Return the celebrity as a typed JSON object.
+pub fn celebrity(celebrity: &Celebrity) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["rank"] = celebrity.rank.into();
+    json["similarity"] = celebrity.similarity.into();
+    json["name"] = celebrity.name.as_str().into();
+    json["gender"] = celebrity.gender.to_string().into();
+    json["image"] = celebrity.image.as_str().into();
+    json["wiki"] = celebrity.wiki.as_str().into();
+    let mut attributes = JsonValue::new_object();
+    for (key, value) in &celebrity.attributes {
+        attributes[key.as_str()] = value.as_str().into();
+    }
+    json["attributes"] = attributes;
+    json["links"] = celebrity.links.iter().map(|link| link.as_str()).collect::<Vec<_>>().into();
+    json
+}
+
+/// Return the error as a JSON object.
+pub fn error(error: &StarByFaceError) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["kind"] = error.kind().into();
+    json["message"] = error.to_string().into();
+    json
+}
+
+/// Return the HTTP status and the JSON document of a lookup.
+pub fn lookup(lookup: &Lookup) -> (u16, JsonValue) {
+    let mut json = JsonValue::new_object();
+    json["warning"] = lookup.warning().into();
+    let status = match lookup.celebrities() {
+        Ok(celebrities) => {
+            json["results"] = celebrities.iter().map(celebrity).collect::<Vec<_>>().into();
+            200
+        },
+        Err(StarByFaceError::Null(_)) => {
+            json["results"] = JsonValue::new_array();
+            200
+        },
+        Err(err) => {
+            json["results"] = JsonValue::new_array();
+            json["error"] = error(&err);
+            match err {
+                StarByFaceError::Error(_) if lookup.raw_body().is_some() => 422,
+                StarByFaceError::NoFace | StarByFaceError::MultipleFaces(_) |
+                StarByFaceError::LowQuality(_) | StarByFaceError::InvalidImage(_) => 422,
+                _ => 502
+            }
+        }
+    };
+    (status, json)
+}
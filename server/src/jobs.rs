@@ -0,0 +1,271 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use json::JsonValue;
+use starbyface::{Batch, Lookup, Source};
+use tokio::sync::{broadcast, Semaphore};
+use crate::encode;
+
+/// Progress event of a job.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// `progress` for a completed image, `done` once the job finished.
+    pub name: &'static str,
+    pub data: String
+}
+
+/// Jobs kept in memory and persisted as JSON files.
+///
+/// Finished jobs are dropped from memory after a while and
+/// read back from their file.
+#[derive(Clone)]
+pub struct Jobs {
+    #[doc(hidden)]
+    dir: PathBuf,
+    #[doc(hidden)]
+    ttl: Duration,
+    #[doc(hidden)]
+    permits: Arc<Semaphore>,
+    #[doc(hidden)]
+    jobs: Arc<Mutex<HashMap<String, Job>>>
+}
+
+#[doc(hidden)]
+struct Job {
+    document: JsonValue,
+    events: broadcast::Sender<Event>,
+    /// Number of changes made to the document.
+    version: u64,
+    /// Version of the document last written to disk.
+    persisted: Arc<Mutex<u64>>,
+    finished: Option<Instant>
+}
+
+impl Jobs {
+
+    /// `dir` Directory job documents are written to.
+    ///
+    /// `max_running` Number of jobs running at once, later
+    /// ones are queued.
+    ///
+    /// `ttl` Time finished jobs are kept in memory.
+    pub fn new(dir: PathBuf, max_running: usize, ttl: Duration) -> Self {
+        Self {
+            dir,
+            ttl,
+            permits: Arc::new(Semaphore::new(max_running.max(1))),
+            jobs: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    /// `batch` Engine running the lookups.
+    ///
+    /// `sources` Images of the job.
+    ///
+    /// `owner` Name of the key starting the job.
+    ///
+    /// Return the job document.
+    pub fn start(&self, batch: Batch, sources: Vec<Source>, owner: Option<&str>) -> JsonValue {
+        self.evict();
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut document = JsonValue::new_object();
+        document["id"] = id.as_str().into();
+        document["status"] = "queued".into();
+        document["total"] = sources.len().into();
+        document["completed"] = 0.into();
+        document["items"] = JsonValue::new_array();
+        if let Some(owner) = owner {
+            document["key"] = owner.into();
+        }
+        let (events, _) = broadcast::channel(sources.len() + 1);
+        self.lock().insert(id.clone(), Job {
+            document: document.clone(),
+            events,
+            version: 1,
+            persisted: Arc::new(Mutex::new(0)),
+            finished: None
+        });
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let (queued, queued_id) = (jobs.clone(), id.clone());
+            let _ = tokio::task::spawn_blocking(move || queued.update(&queued_id, |_| None)).await;
+            let permit = match jobs.permits.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return
+            };
+            let _ = tokio::task::spawn_blocking(move || {
+                jobs.update(&id, |document| {
+                    document["status"] = "running".into();
+                    None
+                });
+                batch.run_with_progress(&sources, |index, lookup| jobs.progress(&id, index, &sources[index], lookup));
+                jobs.finish(&id);
+                drop(permit);
+            }).await;
+        });
+        document
+    }
+
+    /// Return the document of a job started by the key named
+    /// `owner`, from memory or from disk.
+    ///
+    /// Jobs found on disk only that didn't finish are
+    /// reported as interrupted.
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Option<JsonValue> {
+        self.evict();
+        if let Some(job) = self.lock().get(id) {
+            return Some(job.document.clone()).filter(|document| is_owner(document, owner))
+        }
+        if !is_id(id) {
+            return None
+        }
+        let data = std::fs::read_to_string(self.dir.join(format!("{}.json", id))).ok()?;
+        let mut document = json::parse(&data).ok().filter(|document| is_owner(document, owner))?;
+        if !is_finished(&document) {
+            document["status"] = "interrupted".into();
+        }
+        Some(document)
+    }
+
+    /// Return the events already sent and a receiver of the next
+    /// ones, none if the job finished.
+    pub fn subscribe(&self, id: &str, owner: Option<&str>) -> Option<(Vec<Event>, Option<broadcast::Receiver<Event>>)> {
+        let job = self.lock().get(id).map(|job| (job.document.clone(), job.events.subscribe()));
+        let (document, receiver) = match job {
+            Some((document, receiver)) if is_owner(&document, owner) => (document, Some(receiver)),
+            Some(_) => return None,
+            None => (self.get(id, owner)?, None)
+        };
+        let mut events: Vec<Event> = document["items"].members()
+            .map(|item| Event { name: "progress", data: item.dump() })
+            .collect();
+        if is_finished(&document) {
+            events.push(Event { name: "done", data: summary(&document).dump() });
+            return Some((events, None))
+        }
+        Some((events, receiver))
+    }
+
+    #[doc(hidden)]
+    fn progress(&self, id: &str, index: usize, source: &Source, lookup: &Lookup) {
+        let (status, mut item) = encode::lookup(lookup);
+        item["index"] = index.into();
+        item["source"] = source.kind().into();
+        item["status"] = status.into();
+        self.update(id, |document| {
+            let completed = document["completed"].as_usize().unwrap_or_default() + 1;
+            document["completed"] = completed.into();
+            let _ = document["items"].push(item.clone());
+            Some(Event { name: "progress", data: item.dump() })
+        });
+    }
+
+    #[doc(hidden)]
+    fn finish(&self, id: &str) {
+        self.update(id, |document| {
+            document["status"] = "done".into();
+            Some(Event { name: "done", data: summary(document).dump() })
+        });
+        if let Some(job) = self.lock().get_mut(id) {
+            job.finished = Some(Instant::now());
+        }
+    }
+
+    /// Apply `change` to the document of a job, send the event it
+    /// returns and write the document outside of the lock.
+    #[doc(hidden)]
+    fn update<F>(&self, id: &str, change: F) where F: FnOnce(&mut JsonValue) -> Option<Event> {
+        let (data, version, persisted) = {
+            let mut jobs = self.lock();
+            let job = match jobs.get_mut(id) {
+                Some(job) => job,
+                None => return
+            };
+            if let Some(event) = change(&mut job.document) {
+                let _ = job.events.send(event);
+            }
+            job.version += 1;
+            (job.document.dump(), job.version, job.persisted.clone())
+        };
+        let mut persisted = lock(&persisted);
+        if *persisted < version {
+            self.persist(id, &data);
+            *persisted = version;
+        }
+    }
+
+    #[doc(hidden)]
+    fn persist(&self, id: &str, data: &str) {
+        let path = self.dir.join(format!("{}.json", id));
+        let temporary = self.dir.join(format!("{}.json.tmp", id));
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&temporary, data))
+            .and_then(|_| std::fs::rename(&temporary, &path));
+        if let Err(err) = result {
+            tracing::error!(job = id, error = %err, "failed to persist job");
+        }
+    }
+
+    /// Drop the jobs that finished more than `ttl` ago.
+    #[doc(hidden)]
+    fn evict(&self) {
+        self.lock().retain(|_, job| match job.finished {
+            Some(finished) => finished.elapsed() < self.ttl,
+            None => true
+        });
+    }
+
+    #[doc(hidden)]
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Job>> {
+        lock(&self.jobs)
+    }
+}
+
+#[doc(hidden)]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+#[doc(hidden)]
+fn summary(document: &JsonValue) -> JsonValue {
+    let mut summary = document.clone();
+    summary.remove("items");
+    summary
+}
+
+/// Return true if the job was started by the key named `owner`,
+/// or by no key if none.
+#[doc(hidden)]
+fn is_owner(document: &JsonValue, owner: Option<&str>) -> bool {
+    document["key"].as_str() == owner
+}
+
+/// Return true if the job is done, or was interrupted.
+#[doc(hidden)]
+fn is_finished(document: &JsonValue) -> bool {
+    document["status"] != "queued" && document["status"] != "running"
+}
+
+#[doc(hidden)]
+fn is_id(id: &str) -> bool {
+    uuid::Uuid::parse_str(id).is_ok()
+}
@@ -0,0 +1,154 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! REST server wrapping the StarByFace lookup API.
+//!
+//! `POST /lookup` accepts a multipart upload or a JSON
+//! `{"url": "..."}` body and `GET /lookup/{id}` returns a
+//! previous lookup.
+//!
+//! `POST /jobs` accepts several images and returns a job id,
+//! `GET /jobs/{id}` returns its state and `GET /jobs/{id}/events`
+//! streams its progress as Server-Sent Events.
+//!
+//! When started with `--keys`, requests need an API key,
+//! lookups and jobs are only returned to the key that created
+//! them and `GET /usage` returns the counters of the key,
+//! persisted in `--jobs-dir`.
+//!
+//! `GET /metrics` returns Prometheus metrics and never
+//! needs an API key.
+//!
+//! Lookup spans are logged to stderr according to `RUST_LOG`.
+mod auth;
+mod config;
+mod encode;
+mod jobs;
+mod routes;
+mod state;
+
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::time::Duration;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use tracing_subscriber::EnvFilter;
+use starbyface::Client;
+use crate::auth::Keys;
+use crate::config::Config;
+use crate::jobs::Jobs;
+use crate::state::AppState;
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,starbyface_server=info")))
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        // The usage is printed as is rather than logged.
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE
+        }
+    };
+    let keys = match config.keys {
+        Some(ref path) => match Keys::load(path, config.jobs_dir.join("usage.json")) {
+            Ok(keys) => Some(keys),
+            Err(message) => {
+                tracing::error!(error = %message, "failed to load keys");
+                return ExitCode::FAILURE
+            }
+        },
+        None => None
+    };
+    let metrics = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0])
+        .and_then(|builder| builder.set_buckets_for_metric(Matcher::Full("starbyface_parsed_cards".to_string()), &[0.0, 1.0, 5.0, 10.0, 20.0, 50.0]))
+        .and_then(|builder| builder.install_recorder());
+    let metrics = match metrics {
+        Ok(metrics) => metrics,
+        Err(err) => {
+            tracing::error!(error = %err, "failed to install the metrics recorder");
+            return ExitCode::FAILURE
+        }
+    };
+    starbyface::describe_metrics();
+    // The blocking client must be created outside of the runtime.
+    let client = Client::new().with_base_url(&config.upstream).with_retries(config.retries);
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            tracing::error!(error = %err, "failed to start the runtime");
+            return ExitCode::FAILURE
+        }
+    };
+    let jobs = Jobs::new(config.jobs_dir.clone(), config.max_jobs, Duration::from_secs(config.job_ttl));
+    let state = AppState::new(client.clone(), jobs, keys.clone(), metrics.clone(), &config);
+    let persisted_keys = keys.clone();
+    let result = runtime.block_on(async move {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                metrics.run_upkeep();
+            }
+        });
+        if let Some(keys) = keys {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    let keys = keys.clone();
+                    let _ = tokio::task::spawn_blocking(move || keys.persist()).await;
+                }
+            });
+        }
+        let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+        tracing::info!(address = %config.bind, "listening");
+        axum::serve(listener, routes::router(state)).with_graceful_shutdown(shutdown()).await
+    });
+    // Usage recorded since the last tick of the persist loop.
+    if let Some(keys) = persisted_keys {
+        keys.persist();
+    }
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!(error = %err, "server failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = terminate.recv() => {}
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::convert::Infallible;
+use std::sync::Arc;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Request, State};
+use axum::extract::multipart::MultipartError;
+use axum::middleware;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use futures_util::stream::{self, StreamExt};
+use json::JsonValue;
+use starbyface::{Batch, Lookup, Source};
+use tokio::sync::broadcast::error::RecvError;
+use crate::auth::{self, ApiKey, QuotaExceeded};
+use crate::encode;
+use crate::jobs::Event;
+use crate::state::AppState;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/lookup", post(create_lookup))
+        .route("/lookup/{id}", get(get_lookup))
+        .route("/jobs", post(create_job).layer(DefaultBodyLimit::max(state.max_job_body)))
+        .route("/jobs/{id}", get(get_job))
+        .route("/jobs/{id}/events", get(job_events))
+        .route("/usage", get(get_usage))
+        .layer(DefaultBodyLimit::max(state.max_body))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::authenticate))
+        .route("/metrics", get(get_metrics))
+        .with_state(state)
+}
+
+/// Return a JSON response.
+pub fn json_response(status: u16, body: String) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, [(CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// Return a JSON error response.
+pub fn error_response(status: u16, kind: &str, message: &str) -> Response {
+    let mut json = JsonValue::new_object();
+    json["error"]["kind"] = kind.into();
+    json["error"]["message"] = message.into();
+    json_response(status, json.dump())
+}
+
+/// Read the images of a request, either a multipart upload
+/// or a JSON `{"url": "..."}` or `{"urls": [...]}` body.
+pub async fn sources(request: Request, state: &AppState) -> Result<Vec<Source>, Response> {
+    let content_type = request.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let mut sources = vec![];
+    if content_type.starts_with("multipart/form-data") {
+        let mut multipart = match Multipart::from_request(request, state).await {
+            Ok(multipart) => multipart,
+            Err(err) => return Err(error_response(400, "bad_request", &err.body_text()))
+        };
+        loop {
+            match multipart.next_field().await {
+                Ok(Some(field)) => {
+                    let file_name = match field.file_name() {
+                        Some(file_name) => file_name.to_string(),
+                        None => continue
+                    };
+                    match field.bytes().await {
+                        Ok(data) => sources.push(Source::Bytes { data: data.to_vec(), file_name }),
+                        Err(err) => return Err(multipart_error(err))
+                    }
+                },
+                Ok(None) => break,
+                Err(err) => return Err(multipart_error(err))
+            }
+        }
+    } else if content_type.starts_with("application/json") {
+        let body = match Bytes::from_request(request, state).await {
+            Ok(body) => body,
+            Err(err) if err.status() == StatusCode::PAYLOAD_TOO_LARGE => return Err(error_response(413, "payload_too_large", &err.body_text())),
+            Err(err) => return Err(error_response(400, "bad_request", &err.body_text()))
+        };
+        let json = match std::str::from_utf8(&body).ok().and_then(|body| json::parse(body).ok()) {
+            Some(json) => json,
+            None => return Err(error_response(400, "bad_request", "Invalid JSON"))
+        };
+        if let Some(url) = json["url"].as_str() {
+            sources.push(Source::Url(url.to_string()))
+        }
+        for url in json["urls"].members() {
+            match url.as_str() {
+                Some(url) => sources.push(Source::Url(url.to_string())),
+                None => return Err(error_response(400, "bad_request", "Invalid \"urls\""))
+            }
+        }
+    } else {
+        return Err(error_response(415, "unsupported_media_type", "Expected multipart/form-data or application/json"))
+    }
+    if sources.is_empty() {
+        return Err(error_response(400, "bad_request", "Missing image"))
+    }
+    Ok(sources)
+}
+
+#[doc(hidden)]
+fn multipart_error(err: MultipartError) -> Response {
+    match err.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => error_response(413, "payload_too_large", &err.body_text()),
+        _ => error_response(400, "bad_request", &err.body_text())
+    }
+}
+
+/// Return the name of the API key of the request, if any.
+fn owner(request: &Request) -> Option<String> {
+    request.extensions().get::<Arc<ApiKey>>().map(|key| key.name.clone())
+}
+
+/// Charge `images` to the API key of the request, if any.
+fn charge(key: Option<&Arc<ApiKey>>, images: usize) -> Result<(), QuotaExceeded> {
+    match key {
+        Some(key) => key.charge(images as u64),
+        None => Ok(())
+    }
+}
+
+async fn create_lookup(State(state): State<AppState>, request: Request) -> Response {
+    let key = request.extensions().get::<Arc<ApiKey>>().cloned();
+    let source = match sources(request, &state).await {
+        Ok(mut sources) if sources.len() == 1 => sources.remove(0),
+        Ok(_) => return error_response(400, "bad_request", "Expected a single image, use /jobs for several"),
+        Err(response) => return response
+    };
+    if let Err(err) = charge(key.as_ref(), 1) {
+        return err.into_response()
+    }
+    let client = state.client.clone();
+    let lookup = match tokio::task::spawn_blocking(move || Lookup::new(&source, &client)).await {
+        Ok(lookup) => lookup,
+        Err(err) => return error_response(500, "internal", &err.to_string())
+    };
+    let id = uuid::Uuid::new_v4().to_string();
+    let (status, mut json) = encode::lookup(&lookup);
+    json["id"] = id.as_str().into();
+    let document = json.dump();
+    state.store(&id, status, document.clone(), key.map(|key| key.name.clone()));
+    json_response(status, document)
+}
+
+async fn get_lookup(State(state): State<AppState>, Path(id): Path<String>, request: Request) -> Response {
+    match state.get(&id, owner(&request).as_deref()) {
+        Some((status, document)) => json_response(status, document),
+        None => error_response(404, "not_found", "Unknown lookup id")
+    }
+}
+
+async fn create_job(State(state): State<AppState>, request: Request) -> Response {
+    let key = request.extensions().get::<Arc<ApiKey>>().cloned();
+    let sources = match sources(request, &state).await {
+        Ok(sources) => sources,
+        Err(response) => return response
+    };
+    if let Err(err) = charge(key.as_ref(), sources.len()) {
+        return err.into_response()
+    }
+    let batch = Batch::new(&state.client).with_concurrency(state.concurrency);
+    json_response(202, state.jobs.start(batch, sources, key.map(|key| key.name.clone()).as_deref()).dump())
+}
+
+async fn get_job(State(state): State<AppState>, Path(id): Path<String>, request: Request) -> Response {
+    match state.jobs.get(&id, owner(&request).as_deref()) {
+        Some(document) => json_response(200, document.dump()),
+        None => error_response(404, "not_found", "Unknown job id")
+    }
+}
+
+async fn get_metrics(State(state): State<AppState>) -> Response {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render()).into_response()
+}
+
+async fn get_usage(request: Request) -> Response {
+    match request.extensions().get::<Arc<ApiKey>>() {
+        Some(key) => json_response(200, key.usage_json().dump()),
+        None => error_response(404, "not_found", "API keys are not configured")
+    }
+}
+
+async fn job_events(State(state): State<AppState>, Path(id): Path<String>, request: Request) -> Response {
+    let (events, receiver) = match state.jobs.subscribe(&id, owner(&request).as_deref()) {
+        Some(subscription) => subscription,
+        None => return error_response(404, "not_found", "Unknown job id")
+    };
+    let next = stream::unfold(receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let receiver = if event.name == "done" { None } else { Some(receiver) };
+                    return Some((event, receiver))
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None
+            }
+        }
+    });
+    let events = stream::iter(events).chain(next)
+        .map(|event: Event| Ok::<_, Infallible>(sse::Event::default().event(event.name).data(event.data)));
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
@@ -0,0 +1,95 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use metrics_exporter_prometheus::PrometheusHandle;
+use starbyface::Client;
+use crate::auth::Keys;
+use crate::config::Config;
+use crate::jobs::Jobs;
+
+/// State shared by every handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub client: Client,
+    pub jobs: Jobs,
+    /// API keys, every request is allowed if none.
+    pub keys: Option<Keys>,
+    /// Renders `GET /metrics`.
+    pub metrics: PrometheusHandle,
+    /// Number of lookups a job runs at once.
+    pub concurrency: usize,
+    /// Largest request body in bytes.
+    pub max_body: usize,
+    /// Largest body of `POST /jobs` in bytes.
+    pub max_job_body: usize,
+    #[doc(hidden)]
+    lookups: Arc<Mutex<Stored>>,
+    #[doc(hidden)]
+    max_stored: usize
+}
+
+#[doc(hidden)]
+#[derive(Default)]
+struct Stored {
+    order: VecDeque<String>,
+    documents: HashMap<String, (u16, String, Option<String>)>
+}
+
+impl AppState {
+    pub fn new(client: Client, jobs: Jobs, keys: Option<Keys>, metrics: PrometheusHandle, config: &Config) -> Self {
+        Self {
+            client,
+            jobs,
+            keys,
+            metrics,
+            concurrency: config.concurrency,
+            max_body: config.max_body,
+            max_job_body: config.max_job_body,
+            lookups: Arc::new(Mutex::new(Stored::default())),
+            max_stored: config.max_stored
+        }
+    }
+
+    /// Keep the status and document of a lookup and the name of
+    /// the key that created it, dropping the oldest one when full.
+    pub fn store(&self, id: &str, status: u16, document: String, owner: Option<String>) {
+        let mut stored = match self.lookups.lock() {
+            Ok(stored) => stored,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        while stored.order.len() >= self.max_stored.max(1) {
+            if let Some(oldest) = stored.order.pop_front() {
+                stored.documents.remove(&oldest);
+            }
+        }
+        stored.order.push_back(id.to_string());
+        stored.documents.insert(id.to_string(), (status, document, owner));
+    }
+
+    /// Return the status and document of a lookup created by
+    /// the key named `owner`.
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Option<(u16, String)> {
+        let stored = match self.lookups.lock() {
+            Ok(stored) => stored,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        match stored.documents.get(id) {
+            Some((status, document, created_by)) if created_by.as_deref() == owner => Some((*status, document.clone())),
+            _ => None
+        }
+    }
+}
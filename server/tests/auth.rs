@@ -0,0 +1,195 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use json::JsonValue;
+use common::{parse, temp_path, Server};
+
+const KEYS: &str = r#"{"keys": [
+    {"key": "ann-key", "name": "ann"},
+    {"key": "bob-key", "name": "bob"},
+    {"key": "slow-key", "name": "slow", "rate": 0.01, "burst": 1},
+    {"key": "quota-key", "name": "quota", "daily_quota": 1}
+]}"#;
+
+fn keys_file(name: &str, keys: &str) -> PathBuf {
+    let path = temp_path(name).with_extension("json");
+    std::fs::write(&path, keys).unwrap();
+    path
+}
+
+fn start(name: &str) -> (Server, PathBuf) {
+    let path = keys_file(name, KEYS);
+    (Server::start(&["--keys", path.to_str().unwrap()]), path)
+}
+
+fn lookup(server: &Server, key: Option<&str>) -> (u16, JsonValue) {
+    let mut request = reqwest::blocking::Client::new()
+        .post(server.url("/lookup"))
+        .header("content-type", "application/json")
+        .body(r#"{"url": "https://example.com/face.jpg"}"#);
+    if let Some(key) = key {
+        request = request.header("x-api-key", key);
+    }
+    parse(request.send().unwrap())
+}
+
+fn get(server: &Server, path: &str, key: &str) -> (u16, JsonValue) {
+    parse(reqwest::blocking::Client::new()
+        .get(server.url(path))
+        .bearer_auth(key)
+        .send()
+        .unwrap())
+}
+
+#[test]
+fn requests_without_a_known_key_are_unauthorized() {
+    let (server, _) = start("keys-401");
+    for key in [None, Some("wrong-key")] {
+        let (status, json) = lookup(&server, key);
+        assert_eq!(status, 401);
+        assert_eq!(json["error"]["kind"], "unauthorized");
+    }
+    assert_eq!(get(&server, "/usage", "wrong-key").0, 401);
+    assert_eq!(server.upstream_hits(), 0);
+    assert_eq!(get(&server, "/usage", "ann-key").0, 200);
+    assert_eq!(reqwest::blocking::get(server.url("/metrics")).unwrap().status(), 200);
+}
+
+#[test]
+fn requests_over_the_rate_are_limited() {
+    let (server, _) = start("keys-429");
+    assert_eq!(lookup(&server, Some("slow-key")).0, 200);
+    let response = reqwest::blocking::Client::new()
+        .get(server.url("/usage"))
+        .header("x-api-key", "slow-key")
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 429);
+    let retry: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!(retry >= 90, "{}", retry);
+    assert_eq!(parse(response).1["error"]["kind"], "rate_limited");
+    assert_eq!(lookup(&server, Some("ann-key")).0, 200);
+}
+
+#[test]
+fn requests_over_the_quota_are_rejected() {
+    let (server, _) = start("keys-quota");
+    assert_eq!(lookup(&server, Some("quota-key")).0, 200);
+    let (status, json) = lookup(&server, Some("quota-key"));
+    assert_eq!(status, 429);
+    assert_eq!(json["error"]["kind"], "quota_exceeded");
+    let (status, usage) = get(&server, "/usage", "quota-key");
+    assert_eq!(status, 200);
+    assert_eq!(usage["name"], "quota");
+    assert_eq!(usage["images_today"], 1);
+    assert_eq!(usage["daily_quota"], 1);
+    assert_eq!(usage["quota_exceeded"], 1);
+    assert_eq!(server.upstream_hits(), 1);
+}
+
+#[test]
+fn jobs_over_the_quota_are_rejected() {
+    let (server, _) = start("keys-job-quota");
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/jobs"))
+        .header("x-api-key", "quota-key")
+        .header("content-type", "application/json")
+        .body(r#"{"urls": ["https://example.com/one.jpg", "https://example.com/two.jpg"]}"#)
+        .send()
+        .unwrap();
+    assert_eq!(parse(response).0, 429);
+    assert_eq!(server.upstream_hits(), 0);
+}
+
+#[test]
+fn lookups_and_jobs_belong_to_their_key() {
+    let (server, _) = start("keys-owner");
+    let (_, lookup) = lookup(&server, Some("ann-key"));
+    let path = format!("/lookup/{}", lookup["id"]);
+    assert_eq!(get(&server, &path, "ann-key"), (200, lookup));
+    assert_eq!(get(&server, &path, "bob-key").0, 404);
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/jobs"))
+        .header("x-api-key", "ann-key")
+        .header("content-type", "application/json")
+        .body(r#"{"urls": ["https://example.com/face.jpg"]}"#)
+        .send()
+        .unwrap();
+    let (_, job) = parse(response);
+    for path in [format!("/jobs/{}", job["id"]), format!("/jobs/{}/events", job["id"])] {
+        assert_eq!(get(&server, &path, "bob-key").0, 404, "{}", path);
+    }
+    assert_eq!(get(&server, &format!("/jobs/{}", job["id"]), "ann-key").1["key"], "ann");
+}
+
+#[test]
+fn usage_survives_restarts() {
+    let path = keys_file("keys-persist", KEYS);
+    let jobs_dir = temp_path("jobs-persist");
+    let args = ["--keys", path.to_str().unwrap(), "--jobs-dir", jobs_dir.to_str().unwrap()];
+    {
+        let server = Server::start(&args);
+        assert_eq!(lookup(&server, Some("quota-key")).0, 200);
+        thread::sleep(Duration::from_millis(1500));
+    }
+    assert!(jobs_dir.join("usage.json").exists());
+    let server = Server::start(&args);
+    assert_eq!(lookup(&server, Some("quota-key")).0, 429);
+    let usage = get(&server, "/usage", "quota-key").1;
+    assert_eq!(usage["images"], 1);
+    assert_eq!(usage["requests"], 3);
+    let _ = std::fs::remove_dir_all(&jobs_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn usage_is_persisted_on_shutdown() {
+    let path = keys_file("keys-shutdown", KEYS);
+    let jobs_dir = temp_path("jobs-shutdown");
+    let mut server = Server::start(&["--keys", path.to_str().unwrap(), "--jobs-dir", jobs_dir.to_str().unwrap()]);
+    assert_eq!(lookup(&server, Some("ann-key")).0, 200);
+    server.terminate();
+    let usage = json::parse(&std::fs::read_to_string(jobs_dir.join("usage.json")).unwrap()).unwrap();
+    assert_eq!(usage["ann"]["images"], 1, "{}", usage);
+    let _ = std::fs::remove_dir_all(&jobs_dir);
+}
+
+/// Return the exit status and stderr of the server started with `keys`.
+fn fails_to_start(name: &str, keys: &str) -> String {
+    let path = keys_file(name, keys);
+    let output = Command::new(env!("CARGO_BIN_EXE_starbyface-server"))
+        .args(["--bind", "127.0.0.1:0", "--keys"])
+        .arg(Path::new(&path))
+        .stdout(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn invalid_keys_fail_at_startup() {
+    assert!(fails_to_start("keys-missing", "{}").contains("non-empty array"));
+    assert!(fails_to_start("keys-empty", r#"{"keys": []}"#).contains("non-empty array"));
+    assert!(fails_to_start("keys-duplicate", r#"{"keys": [{"key": "a", "name": "one"}, {"key": "a", "name": "two"}]}"#).contains("duplicate"));
+    assert!(fails_to_start("keys-name", r#"{"keys": [{"key": "a", "name": "one"}, {"key": "b", "name": "one"}]}"#).contains("used twice"));
+    assert!(fails_to_start("keys-rate", r#"{"keys": [{"key": "a", "rate": 0}]}"#).contains("Rate"));
+}
@@ -0,0 +1,52 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use common::{form, jpeg, multipart_body, parse, post_raw, Server};
+
+const LARGE: usize = 3 * 1024 * 1024;
+
+#[test]
+fn accepts_uploads_larger_than_two_megabytes() {
+    let server = Server::start(&[]);
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/lookup"))
+        .multipart(form(vec![jpeg(LARGE)]))
+        .send()
+        .unwrap();
+    let (status, json) = parse(response);
+    assert_eq!(status, 200, "{}", json);
+    assert_eq!(json["results"][0]["name"], "Ann");
+}
+
+#[test]
+fn rejects_uploads_larger_than_max_body() {
+    let server = Server::start(&["--max-body", "1048576"]);
+    let body = multipart_body("boundary", &[jpeg(LARGE)]);
+    let (status, json) = post_raw(&server, "/lookup", "multipart/form-data; boundary=boundary", body);
+    assert_eq!(status, 413);
+    assert_eq!(json["error"]["kind"], "payload_too_large");
+    assert_eq!(server.upstream_hits(), 0);
+}
+
+#[test]
+fn rejects_json_larger_than_max_body() {
+    let server = Server::start(&["--max-body", "1024"]);
+    let body = format!(r#"{{"url": "https://example.com/{}.jpg"}}"#, "a".repeat(2048));
+    let (status, json) = post_raw(&server, "/lookup", "application/json", body.into_bytes());
+    assert_eq!(status, 413);
+    assert_eq!(json["error"]["kind"], "payload_too_large");
+}
@@ -0,0 +1,219 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Results page answered by the mock upstream.
+pub const PAGE: &str = r#"<div id="female-celebs-result"><div class="card" similarity="90">
+    <div class="bar"><div class="fill"></div>
+    </div>
+    </div>
+    <a href="https://en.wikipedia.org/wiki/Ann" target="_blank">
+    <img class="photo" src="/img/Ann.jpg" />
+    <p class="name">Ann</p></div>"#;
+
+/// StarByFace answering every request with `PAGE`.
+pub struct Upstream {
+    pub base_url: String,
+    hits: Arc<AtomicUsize>
+}
+
+impl Upstream {
+
+    /// `delay` Time waited before answering each request.
+    pub fn start(delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream);
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                            break
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                length = value.trim().parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    let _ = reader.read_exact(&mut body);
+                    thread::sleep(delay);
+                    let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", PAGE.len(), PAGE);
+                    let _ = reader.get_mut().write_all(response.as_bytes());
+                });
+            }
+        });
+        Self { base_url, hits }
+    }
+
+    /// Return the number of requests received.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+/// `starbyface-server` running on a free port, killed on drop.
+pub struct Server {
+    pub base_url: String,
+    pub jobs_dir: PathBuf,
+    child: Child,
+    #[allow(unused)]
+    upstream: Upstream
+}
+
+impl Server {
+
+    /// `args` Arguments besides `--bind`, `--upstream` and `--jobs-dir`.
+    pub fn start(args: &[&str]) -> Self {
+        Self::with_upstream(Upstream::start(Duration::ZERO), args)
+    }
+
+    pub fn with_upstream(upstream: Upstream, args: &[&str]) -> Self {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let jobs_dir = temp_path(&format!("jobs-{}", address.port()));
+        let child = Command::new(env!("CARGO_BIN_EXE_starbyface-server"))
+            .arg("--bind").arg(address.to_string())
+            .arg("--upstream").arg(&upstream.base_url)
+            .arg("--jobs-dir").arg(&jobs_dir)
+            .args(args)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            assert!(start.elapsed() < Duration::from_secs(10), "server didn't start");
+            thread::sleep(Duration::from_millis(20));
+        }
+        Self { base_url: format!("http://{}", address), jobs_dir, child, upstream }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub fn upstream_hits(&self) -> usize {
+        self.upstream.hits()
+    }
+
+    /// Send SIGTERM and wait for a graceful exit.
+    #[cfg(unix)]
+    pub fn terminate(&mut self) {
+        let status = Command::new("kill").arg("-TERM").arg(self.child.id().to_string()).status().unwrap();
+        assert!(status.success());
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.jobs_dir);
+    }
+}
+
+/// Return a path in the temporary directory unique to this process.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("starbyface-server-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Return a JPEG of about `length` bytes, most of them scan data.
+pub fn jpeg(length: usize) -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 1, 1, 0, 0, 63, 0]);
+    jpeg.resize(jpeg.len() + length, 0x12);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+/// Return a multipart form of images named `face-N.jpg`.
+pub fn form(images: Vec<Vec<u8>>) -> reqwest::blocking::multipart::Form {
+    let mut form = reqwest::blocking::multipart::Form::new();
+    for (index, image) in images.into_iter().enumerate() {
+        let part = reqwest::blocking::multipart::Part::bytes(image)
+            .file_name(format!("face-{}.jpg", index + 1))
+            .mime_str("image/jpeg")
+            .unwrap();
+        form = form.part(format!("image-{}", index + 1), part);
+    }
+    form
+}
+
+/// Post `body` over a raw connection while reading the response,
+/// so a response sent before the whole body was read isn't lost.
+///
+/// Return the status and JSON body of the response.
+pub fn post_raw(server: &Server, path: &str, content_type: &str, body: Vec<u8>) -> (u16, json::JsonValue) {
+    let mut stream = TcpStream::connect(server.base_url.trim_start_matches("http://")).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let head = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", path, content_type, body.len());
+    thread::spawn(move || {
+        let _ = writer.write_all(head.as_bytes()).and_then(|_| writer.write_all(&body));
+    });
+    let mut response = vec![];
+    let mut buffer = [0; 8192];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read])
+        }
+    }
+    let response = String::from_utf8_lossy(&response).to_string();
+    let status = response.split(' ').nth(1).and_then(|status| status.parse().ok()).unwrap_or_default();
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+    (status, json::parse(body).unwrap_or(json::JsonValue::Null))
+}
+
+/// Return a multipart body of images named `face-N.jpg`.
+pub fn multipart_body(boundary: &str, images: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = vec![];
+    for (index, image) in images.iter().enumerate() {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"image-{}\"; filename=\"face-{}.jpg\"\r\nContent-Type: image/jpeg\r\n\r\n", boundary, index + 1, index + 1).as_bytes());
+        body.extend_from_slice(image);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// Return the status and JSON body of a response.
+pub fn parse(response: reqwest::blocking::Response) -> (u16, json::JsonValue) {
+    let status = response.status().as_u16();
+    (status, json::parse(&response.text().unwrap()).unwrap())
+}
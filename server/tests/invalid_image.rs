@@ -0,0 +1,32 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use common::{form, parse, Server};
+
+#[test]
+fn rejects_malformed_images() {
+    let server = Server::start(&[]);
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/lookup"))
+        .multipart(form(vec![vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x40, b'E']]))
+        .send()
+        .unwrap();
+    let (status, json) = parse(response);
+    assert_eq!(status, 422, "{}", json);
+    assert_eq!(json["error"]["kind"], "invalid_image");
+    assert_eq!(server.upstream_hits(), 0);
+}
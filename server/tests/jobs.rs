@@ -0,0 +1,112 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use json::JsonValue;
+use common::{jpeg, multipart_body, parse, post_raw, Server, Upstream};
+
+fn start_job(server: &Server, urls: &[&str]) -> JsonValue {
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/jobs"))
+        .header("content-type", "application/json")
+        .body(json::object!{ "urls": urls }.dump())
+        .send()
+        .unwrap();
+    let (status, json) = parse(response);
+    assert_eq!(status, 202, "{}", json);
+    json
+}
+
+fn get_job(server: &Server, id: &str) -> (u16, JsonValue) {
+    parse(reqwest::blocking::get(server.url(&format!("/jobs/{}", id))).unwrap())
+}
+
+fn wait_done(server: &Server, id: &str) -> JsonValue {
+    let start = Instant::now();
+    loop {
+        let (status, json) = get_job(server, id);
+        assert_eq!(status, 200, "{}", json);
+        if json["status"] == "done" {
+            return json
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "{}", json);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn batches_may_be_larger_than_max_body() {
+    let server = Server::start(&["--max-body", "1048576"]);
+    let images = vec![jpeg(600 * 1024), jpeg(600 * 1024), jpeg(600 * 1024)];
+    let body = multipart_body("boundary", &images);
+    let (status, json) = post_raw(&server, "/lookup", "multipart/form-data; boundary=boundary", body.clone());
+    assert_eq!(status, 413, "{}", json);
+    let (status, json) = post_raw(&server, "/jobs", "multipart/form-data; boundary=boundary", body);
+    assert_eq!(status, 202, "{}", json);
+    let job = wait_done(&server, json["id"].as_str().unwrap());
+    assert_eq!(job["completed"], 3);
+    assert!(job["items"].members().all(|item| item["status"] == 200));
+}
+
+#[test]
+fn batches_larger_than_max_job_body_are_rejected() {
+    let server = Server::start(&["--max-job-body", "1048576"]);
+    let body = multipart_body("boundary", &[jpeg(600 * 1024), jpeg(600 * 1024)]);
+    let (status, json) = post_raw(&server, "/jobs", "multipart/form-data; boundary=boundary", body);
+    assert_eq!(status, 413, "{}", json);
+}
+
+#[test]
+fn jobs_over_the_limit_are_queued() {
+    let server = Server::with_upstream(Upstream::start(Duration::from_secs(2)), &["--max-jobs", "1"]);
+    let first = start_job(&server, &["https://example.com/one.jpg"]);
+    let second = start_job(&server, &["https://example.com/two.jpg"]);
+    assert_eq!(second["status"], "queued");
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(get_job(&server, first["id"].as_str().unwrap()).1["status"], "running");
+    assert_eq!(get_job(&server, second["id"].as_str().unwrap()).1["status"], "queued");
+    assert_eq!(server.upstream_hits(), 1);
+    wait_done(&server, first["id"].as_str().unwrap());
+    wait_done(&server, second["id"].as_str().unwrap());
+    assert_eq!(server.upstream_hits(), 2);
+}
+
+#[test]
+fn finished_jobs_are_read_back_from_disk() {
+    let server = Server::start(&["--job-ttl", "0"]);
+    let id = start_job(&server, &["https://example.com/one.jpg"])["id"].as_str().unwrap().to_string();
+    let done = wait_done(&server, &id);
+    thread::sleep(Duration::from_millis(50));
+    let path = server.jobs_dir.join(format!("{}.json", id));
+    assert_eq!(json::parse(&std::fs::read_to_string(&path).unwrap()).unwrap(), done);
+    assert_eq!(get_job(&server, &id), (200, done));
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(get_job(&server, &id).0, 404);
+}
+
+#[test]
+fn unfinished_jobs_on_disk_are_interrupted() {
+    let server = Server::start(&[]);
+    let id = "00000000-0000-4000-8000-000000000000";
+    std::fs::create_dir_all(&server.jobs_dir).unwrap();
+    let document = json::object!{ "id": id, "status": "running", "total": 2, "completed": 1, "items": [] };
+    std::fs::write(server.jobs_dir.join(format!("{}.json", id)), document.dump()).unwrap();
+    let (status, json) = get_job(&server, id);
+    assert_eq!(status, 200);
+    assert_eq!(json["status"], "interrupted");
+}
@@ -0,0 +1,52 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use common::{parse, Server};
+
+/// Look up a URL and return the status and the scraped metrics.
+fn lookup(server: &Server) -> (u16, String) {
+    let response = reqwest::blocking::Client::new()
+        .post(server.url("/lookup"))
+        .header("content-type", "application/json")
+        .body(r#"{"url": "https://example.com/face.jpg"}"#)
+        .send()
+        .unwrap();
+    let status = parse(response).0;
+    (status, reqwest::blocking::get(server.url("/metrics")).unwrap().text().unwrap())
+}
+
+#[test]
+fn lookups_are_counted_and_timed() {
+    let server = Server::start(&[]);
+    let (status, metrics) = lookup(&server);
+    assert_eq!(status, 200);
+    assert!(metrics.contains("starbyface_lookups_total{source=\"url\",outcome=\"ok\"} 1"), "{}", metrics);
+    assert!(metrics.contains("starbyface_upstream_requests_total{source=\"url\"} 1"), "{}", metrics);
+    assert!(metrics.contains("starbyface_upstream_latency_seconds_count{source=\"url\"} 1"), "{}", metrics);
+    assert!(metrics.contains("starbyface_parsed_cards_sum{source=\"url\"} 1"), "{}", metrics);
+}
+
+#[test]
+fn retries_are_counted() {
+    // The last --upstream wins, pointing the server at a closed port.
+    let server = Server::start(&["--upstream", "http://127.0.0.1:1", "--retries", "1"]);
+    let (status, metrics) = lookup(&server);
+    assert_eq!(status, 502);
+    assert!(metrics.contains("starbyface_upstream_retries_total{source=\"url\",reason=\"error\"} 1"), "{}", metrics);
+    assert!(metrics.contains("starbyface_upstream_requests_total{source=\"url\"} 2"), "{}", metrics);
+    assert!(metrics.contains("starbyface_lookups_total{source=\"url\",outcome=\"error\"} 1"), "{}", metrics);
+}
@@ -0,0 +1,283 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::fmt::{Display, Formatter};
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+use image::imageops::FilterType;
+use crate::{BoundingBox, FaceDetector, StarByFaceError};
+use crate::preprocess::{decode, encode};
+
+#[doc(hidden)]
+const EYE_SEARCH_SIZE: u32 = 128;
+
+/// Face an image was aligned on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    /// Face in the image with its EXIF orientation applied.
+    pub face: BoundingBox,
+    /// Estimated center of the eye on the left of the image.
+    pub left_eye: (f64, f64),
+    /// Estimated center of the eye on the right of the image.
+    pub right_eye: (f64, f64),
+    /// Confidence in the eye positions, from 0 to 1, the
+    /// contrast of the eyes to the rest of the upper face.
+    pub confidence: f64,
+    /// Rotation applied to level the eyes, in degrees, 0 if the
+    /// confidence was too low or the eyes too tilted.
+    pub angle: f64
+}
+
+impl Display for Alignment {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "face {}x{} at {},{}, eye confidence {:.2}, angle {:.1}",
+               self.face.width,
+               self.face.height,
+               self.face.x,
+               self.face.y,
+               self.confidence,
+               self.angle)
+    }
+}
+
+/// Crops files and bytes to their face before they are
+/// uploaded, rotated so that the eyes are level.
+///
+/// No facial landmarks are detected: the eyes are guessed as
+/// the darkest regions of the upper half of the face found by
+/// the detector. Glasses, shadows, hair or closed eyes can
+/// mislead the guess, the confidence then stays low and the
+/// face is only cropped.
+#[derive(Debug, Clone)]
+pub struct Aligner {
+    #[doc(hidden)]
+    detector: FaceDetector,
+    #[doc(hidden)]
+    size: u32,
+    #[doc(hidden)]
+    padding: f64,
+    #[doc(hidden)]
+    max_angle: f64,
+    #[doc(hidden)]
+    min_confidence: f64
+}
+
+impl Aligner {
+
+    /// `detector` Detector used to find the face.
+    pub fn new(detector: &FaceDetector) -> Self {
+        Self {
+            detector: detector.clone(),
+            size: 512,
+            padding: 0.4,
+            max_angle: 30.0,
+            min_confidence: 0.1
+        }
+    }
+
+    /// `size` Width and height of the uploaded crop, 512 by default.
+    pub fn with_size(mut self, size: u32) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// `padding` Margin added on each side of the face, as a
+    /// fraction of its size, 0.4 by default.
+    pub fn with_padding(mut self, padding: f64) -> Self {
+        self.padding = padding.max(0.0);
+        self
+    }
+
+    /// `max_angle` Largest rotation applied, in degrees, 30 by
+    /// default. Eyes tilted further are assumed misplaced and
+    /// the face is cropped without rotation.
+    pub fn with_max_angle(mut self, max_angle: f64) -> Self {
+        self.max_angle = max_angle.abs();
+        self
+    }
+
+    /// `min_confidence` Lowest confidence in the eye positions for
+    /// which the face is rotated, 0.1 by default. Faces with less
+    /// contrasted eyes are cropped without rotation.
+    pub fn with_min_confidence(mut self, min_confidence: f64) -> Self {
+        self.min_confidence = min_confidence.clamp(0.0, 1.0);
+        self
+    }
+
+    /// `data` Image content.
+    ///
+    /// Return the aligned crop of the face with the highest
+    /// detection score as JPEG, `None` if there is no face.
+    pub fn align(&self, data: &[u8]) -> Result<Option<(Vec<u8>, Alignment)>, StarByFaceError> {
+        let image = decode(data)?;
+        let face = match self.detector.detect_image(&image).first() {
+            Some(face) => face.bounding_box,
+            None => return Ok(None)
+        };
+        let (left_eye, right_eye, confidence) = eyes(&image, face);
+        let angle = angle(left_eye, right_eye, confidence, self.min_confidence, self.max_angle);
+        if confidence < self.min_confidence {
+            tracing::debug!(confidence, "eyes not found, face not rotated");
+        }
+        let center = (face.x as f64 + face.width as f64 / 2.0, face.y as f64 + face.height as f64 / 2.0);
+        let side = face.width.max(face.height) as f64 * (1.0 + 2.0 * self.padding);
+        let crop = sample(&image.to_rgb8(), center, side, angle.to_radians(), self.size);
+        let alignment = Alignment { face, left_eye, right_eye, confidence, angle };
+        Ok(Some((encode(&DynamicImage::ImageRgb8(crop))?, alignment)))
+    }
+}
+
+/// Return the angle of the eyes in degrees, or 0 if the
+/// `confidence` is below `min_confidence` or the angle above
+/// `max_angle`.
+#[doc(hidden)]
+fn angle(left_eye: (f64, f64), right_eye: (f64, f64), confidence: f64, min_confidence: f64, max_angle: f64) -> f64 {
+    let angle = (right_eye.1 - left_eye.1).atan2(right_eye.0 - left_eye.0).to_degrees();
+    if confidence < min_confidence || angle.abs() > max_angle {
+        0.0
+    } else {
+        angle
+    }
+}
+
+/// Return the estimated centers of the eyes of the face and the
+/// confidence in them, the lowest of both eyes.
+#[doc(hidden)]
+fn eyes(image: &DynamicImage, face: BoundingBox) -> ((f64, f64), (f64, f64), f64) {
+    let gray = image.crop_imm(face.x, face.y, face.width.max(1), face.height.max(1))
+        .resize_exact(EYE_SEARCH_SIZE, EYE_SEARCH_SIZE, FilterType::Triangle)
+        .blur(1.5)
+        .to_luma8();
+    let scale = (face.width as f64 / EYE_SEARCH_SIZE as f64, face.height as f64 / EYE_SEARCH_SIZE as f64);
+    let to_image = |(x, y): (f64, f64)| (face.x as f64 + x * scale.0, face.y as f64 + y * scale.1);
+    let top = EYE_SEARCH_SIZE / 5;
+    let bottom = EYE_SEARCH_SIZE / 2;
+    let middle = EYE_SEARCH_SIZE / 2;
+    let (left, left_confidence) = darkest(&gray, EYE_SEARCH_SIZE / 10, middle, top, bottom);
+    let (right, right_confidence) = darkest(&gray, middle, EYE_SEARCH_SIZE - EYE_SEARCH_SIZE / 10, top, bottom);
+    (to_image(left), to_image(right), left_confidence.min(right_confidence))
+}
+
+/// Return the centroid of the darkest tenth of the pixels of a
+/// region and the confidence in it, their contrast to the region
+/// lowered as they spread out.
+#[doc(hidden)]
+fn darkest(gray: &GrayImage, left: u32, right: u32, top: u32, bottom: u32) -> ((f64, f64), f64) {
+    let mut pixels: Vec<(u8, u32, u32)> = vec![];
+    for y in top..bottom {
+        for x in left..right {
+            pixels.push((gray.get_pixel(x, y)[0], x, y));
+        }
+    }
+    pixels.sort_unstable();
+    let darkest = &pixels[..(pixels.len() / 10).max(1)];
+    let count = darkest.len() as f64;
+    let centroid = (darkest.iter().map(|&(_, x, _)| x as f64 + 0.5).sum::<f64>() / count,
+                    darkest.iter().map(|&(_, _, y)| y as f64 + 0.5).sum::<f64>() / count);
+    let mean = |pixels: &[(u8, u32, u32)]| pixels.iter().map(|&(value, _, _)| value as f64).sum::<f64>() / pixels.len() as f64;
+    let contrast = (mean(&pixels) - mean(darkest)) / 255.0;
+    let spread = (darkest.iter()
+        .map(|&(_, x, y)| (x as f64 + 0.5 - centroid.0).powi(2) + (y as f64 + 0.5 - centroid.1).powi(2))
+        .sum::<f64>() / count).sqrt();
+    let radius = ((right - left) as f64).hypot((bottom - top) as f64) / 2.0;
+    (centroid, contrast * (1.0 - spread / radius).max(0.0))
+}
+
+/// Return a `size` square of the `side` square centered on
+/// `center` and rotated by `angle`, sampled bilinearly.
+#[doc(hidden)]
+fn sample(image: &RgbImage, center: (f64, f64), side: f64, angle: f64, size: u32) -> RgbImage {
+    let (sin, cos) = angle.sin_cos();
+    let step = side / size as f64;
+    let pixel = |x: i64, y: i64| -> [f64; 3] {
+        let x = x.clamp(0, image.width() as i64 - 1) as u32;
+        let y = y.clamp(0, image.height() as i64 - 1) as u32;
+        let Rgb(rgb) = *image.get_pixel(x, y);
+        [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64]
+    };
+    RgbImage::from_fn(size, size, |u, v| {
+        let dx = (u as f64 + 0.5) * step - side / 2.0;
+        let dy = (v as f64 + 0.5) * step - side / 2.0;
+        let x = center.0 + dx * cos - dy * sin - 0.5;
+        let y = center.1 + dx * sin + dy * cos - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let (a, b, c, d) = (pixel(x0, y0), pixel(x0 + 1, y0), pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1));
+        let mut rgb = [0u8; 3];
+        for channel in 0..3 {
+            let top = a[channel] * (1.0 - fx) + b[channel] * fx;
+            let bottom = c[channel] * (1.0 - fx) + d[channel] * fx;
+            rgb[channel] = (top * (1.0 - fy) + bottom * fy).round().clamp(0.0, 255.0) as u8;
+        }
+        Rgb(rgb)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+    use crate::BoundingBox;
+    use super::{angle, eyes};
+
+    /// Return a light face of `size` with dark eyes of radius
+    /// `radius` centered on `left` and `right`.
+    fn face(size: u32, left: (f64, f64), right: (f64, f64), radius: f64) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(size, size, |x, y| {
+            let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+            let eye = [left, right].iter().any(|&(eye_x, eye_y)| (x - eye_x).hypot(y - eye_y) <= radius);
+            if eye { Luma([30]) } else { Luma([200]) }
+        }))
+    }
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64)) {
+        assert!((actual.0 - expected.0).abs() < 2.0 && (actual.1 - expected.1).abs() < 2.0,
+                "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn finds_level_eyes() {
+        let image = face(256, (80.0, 90.0), (176.0, 90.0), 16.0);
+        let (left, right, confidence) = eyes(&image, BoundingBox { x: 0, y: 0, width: 256, height: 256 });
+        assert_near(left, (80.0, 90.0));
+        assert_near(right, (176.0, 90.0));
+        assert!(confidence > 0.3, "{}", confidence);
+        assert!(angle(left, right, confidence, 0.1, 30.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn finds_tilted_eyes_in_a_face() {
+        let image = face(400, (190.0, 150.0), (290.0, 175.0), 16.0);
+        let (left, right, confidence) = eyes(&image, BoundingBox { x: 100, y: 60, width: 256, height: 256 });
+        assert_near(left, (190.0, 150.0));
+        assert_near(right, (290.0, 175.0));
+        let angle = angle(left, right, confidence, 0.1, 30.0);
+        assert!((angle - 25.0f64.atan2(100.0).to_degrees()).abs() < 2.0, "{}", angle);
+    }
+
+    #[test]
+    fn has_no_confidence_in_uniform_faces() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(256, 256, Luma([128])));
+        let (left, right, confidence) = eyes(&image, BoundingBox { x: 0, y: 0, width: 256, height: 256 });
+        assert!(confidence < 0.01, "{}", confidence);
+        assert_eq!(angle(left, right, confidence, 0.1, 30.0), 0.0);
+    }
+
+    #[test]
+    fn skips_rotation() {
+        assert!((angle((0.0, 0.0), (10.0, 10.0), 0.5, 0.1, 60.0) - 45.0).abs() < 1e-9);
+        assert_eq!(angle((0.0, 0.0), (10.0, 10.0), 0.05, 0.1, 60.0), 0.0);
+        assert_eq!(angle((0.0, 0.0), (10.0, 10.0), 0.5, 0.1, 30.0), 0.0);
+    }
+}
@@ -0,0 +1,502 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::io::Cursor;
+use std::iter::Take;
+use std::time::Duration;
+use image::{AnimationDecoder, DynamicImage, Frame, Frames, ImageDecoder, ImageFormat, RgbImage, RgbaImage};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use crate::{Batch, Celebrity, Client, ConsensusCelebrity, Lookup, Source, StarByFaceError};
+use crate::consensus::aggregate;
+use crate::preprocess::encode;
+use crate::quality::sharpness;
+#[cfg(feature = "face")]
+use crate::FaceDetector;
+
+/// Frames of an animated image looked up by an `Animation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameSelection {
+    /// This many frames evenly spaced across the animation.
+    Sample(usize),
+    /// The sharpest frame, with a face if a face detector is set.
+    Sharpest
+}
+
+/// Index, image and delay of a frame selected for lookup.
+#[doc(hidden)]
+type SelectedFrame = (usize, DynamicImage, Duration);
+
+/// Lookup of one frame of an animated image.
+#[derive(Debug, Clone)]
+pub struct FrameLookup {
+    /// Index of the frame, starting at 0.
+    pub frame: usize,
+    /// Time the frame is shown.
+    pub delay: Duration,
+    pub lookup: Lookup
+}
+
+/// Result of an `Animation`.
+#[derive(Debug, Clone)]
+pub struct AnimationResult {
+    /// Number of frames of the image, at most the maximum
+    /// number of frames, 1 if it isn't animated.
+    pub frame_count: usize,
+    /// Lookup of each selected frame.
+    pub frames: Vec<FrameLookup>,
+    /// Celebrities of every frame, best first, the photo of
+    /// their provenance being the index of the frame.
+    pub celebrities: Vec<ConsensusCelebrity>
+}
+
+/// Looks up frames of animated GIF, APNG and WebP images,
+/// which would otherwise be uploaded as they are.
+///
+/// Frames are decoded one at a time and only the selected
+/// ones are kept. Images that aren't animated are looked up
+/// as one frame.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    selection: FrameSelection,
+    #[cfg(feature = "face")]
+    #[doc(hidden)]
+    face_detector: Option<FaceDetector>,
+    #[doc(hidden)]
+    max_frames: usize,
+    #[doc(hidden)]
+    max_pixels: u64,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl Animation {
+
+    /// `client` Client used to send the requests.
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            selection: FrameSelection::Sample(4),
+            #[cfg(feature = "face")]
+            face_detector: None,
+            max_frames: 1000,
+            max_pixels: 16_777_216,
+            concurrency: 4
+        }
+    }
+
+    /// `selection` Frames looked up, `FrameSelection::Sample(4)`
+    /// by default.
+    pub fn with_selection(mut self, selection: FrameSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// `face_detector` Detector used to skip frames without a
+    /// face when looking for the sharpest frame.
+    #[cfg(feature = "face")]
+    pub fn with_face_detector(mut self, face_detector: &FaceDetector) -> Self {
+        self.face_detector = Some(face_detector.clone());
+        self
+    }
+
+    /// `max_frames` Largest number of frames decoded, 1000 by
+    /// default. Later frames are ignored.
+    pub fn with_max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = max_frames.max(1);
+        self
+    }
+
+    /// `max_pixels` Largest width times height of the frames,
+    /// 16777216 by default. Larger animations fail with
+    /// `InvalidImage`.
+    pub fn with_max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `source` Image file or bytes.
+    ///
+    /// Return the lookup of each selected frame and the
+    /// celebrities of every frame, or the error of the first
+    /// frame if none succeeded.
+    pub fn run(&self, source: &Source) -> Result<AnimationResult, StarByFaceError> {
+        if let Source::Url(_) = *source {
+            return Err(StarByFaceError::Error(String::from("Frames can only be read from files and bytes")))
+        }
+        let (data, file_name) = source.read()?;
+        let (frame_count, frames) = self.select(&data)?;
+        let frame_count = frame_count.max(1);
+        let (selected, sources) = if frames.is_empty() {
+            (vec![(0, Duration::ZERO)], vec![Source::Bytes { data, file_name }])
+        } else {
+            let stem = match file_name.rsplit_once('.') {
+                Some((stem, _)) => stem.to_string(),
+                None => file_name
+            };
+            let mut selected = vec![];
+            let mut sources = vec![];
+            for (index, image, delay) in frames {
+                selected.push((index, delay));
+                sources.push(Source::Bytes {
+                    data: encode(&image)?,
+                    file_name: format!("{}-frame-{}.jpg",stem,index + 1)
+                });
+            }
+            (selected, sources)
+        };
+        let lookups = Batch::new(&self.client).with_concurrency(self.concurrency).run(&sources);
+        let results: Vec<(usize, Vec<Celebrity>)> = selected.iter()
+            .zip(&lookups)
+            .filter_map(|(&(frame, _), lookup)| lookup.celebrities().ok().map(|celebrities| (frame, celebrities)))
+            .collect();
+        if results.is_empty() {
+            return match lookups.first().map(|lookup| lookup.celebrities()) {
+                Some(Err(err)) => Err(err),
+                _ => Err(StarByFaceError::Null(String::from("No frame to look up")))
+            }
+        }
+        tracing::info!(frames = frame_count, selected = selected.len(), succeeded = results.len(), "looked up frames");
+        Ok(AnimationResult {
+            frame_count,
+            celebrities: aggregate(&results),
+            frames: selected.into_iter()
+                .zip(lookups)
+                .map(|((frame, delay), lookup)| FrameLookup { frame, delay, lookup })
+                .collect()
+        })
+    }
+
+    /// Return the number of frames of the image and the selected
+    /// frames with their index and delay, none if it isn't animated.
+    #[doc(hidden)]
+    fn select(&self, data: &[u8]) -> Result<(usize, Vec<SelectedFrame>), StarByFaceError> {
+        match self.selection {
+            FrameSelection::Sample(count) => {
+                let mut frame_count = 0;
+                for frame in self.frames(data)?.into_iter().flatten() {
+                    frame.map_err(invalid)?;
+                    frame_count += 1;
+                }
+                if frame_count < 2 {
+                    return Ok((frame_count, vec![]))
+                }
+                let count = count.clamp(1, frame_count);
+                let mut indices: Vec<usize> = (0..count).map(|index| index * frame_count / count).collect();
+                indices.dedup();
+                let mut selected = vec![];
+                for (index, frame) in self.frames(data)?.into_iter().flatten().enumerate() {
+                    let frame = frame.map_err(invalid)?;
+                    if indices.binary_search(&index).is_ok() {
+                        let (image, delay) = convert(frame);
+                        selected.push((index, image, delay));
+                        if selected.len() == indices.len() {
+                            break
+                        }
+                    }
+                }
+                Ok((frame_count, selected))
+            },
+            FrameSelection::Sharpest => {
+                let mut frame_count = 0;
+                let mut sharpest: Option<(f64, usize, DynamicImage, Duration)> = None;
+                for frame in self.frames(data)?.into_iter().flatten() {
+                    let (image, delay) = convert(frame.map_err(invalid)?);
+                    let score = sharpness(&image);
+                    if sharpest.as_ref().is_none_or(|&(best, ..)| score > best) && self.has_face(&image) {
+                        sharpest = Some((score, frame_count, image, delay));
+                    }
+                    frame_count += 1;
+                }
+                match sharpest {
+                    _ if frame_count < 2 => Ok((frame_count, vec![])),
+                    Some((_, index, image, delay)) => Ok((frame_count, vec![(index, image, delay)])),
+                    None => Err(StarByFaceError::NoFace)
+                }
+            }
+        }
+    }
+
+    /// Return the frames of an animated GIF, APNG or WebP image,
+    /// up to the maximum number of frames, none for other images.
+    #[doc(hidden)]
+    fn frames<'a>(&self, data: &'a [u8]) -> Result<Option<Take<Frames<'a>>>, StarByFaceError> {
+        let (dimensions, frames) = match image::guess_format(data) {
+            Ok(ImageFormat::Gif) => {
+                let decoder = GifDecoder::new(Cursor::new(data)).map_err(invalid)?;
+                (decoder.dimensions(), decoder.into_frames())
+            },
+            Ok(ImageFormat::Png) => {
+                let decoder = PngDecoder::new(Cursor::new(data)).map_err(invalid)?;
+                if decoder.is_apng().map_err(invalid)? {
+                    (decoder.dimensions(), decoder.apng().map_err(invalid)?.into_frames())
+                } else {
+                    return Ok(None)
+                }
+            },
+            Ok(ImageFormat::WebP) => {
+                let decoder = WebPDecoder::new(Cursor::new(data)).map_err(invalid)?;
+                if decoder.has_animation() {
+                    (decoder.dimensions(), decoder.into_frames())
+                } else {
+                    return Ok(None)
+                }
+            },
+            _ => return Ok(None)
+        };
+        let (width, height) = dimensions;
+        if width as u64 * height as u64 > self.max_pixels {
+            return Err(StarByFaceError::InvalidImage(format!("Animation of {}x{} pixels exceeds the limit of {} pixels",width,height,self.max_pixels)))
+        }
+        Ok(Some(frames.take(self.max_frames)))
+    }
+
+    /// Return true if the image has a face, or no face detector is set.
+    #[cfg(feature = "face")]
+    #[doc(hidden)]
+    fn has_face(&self, image: &DynamicImage) -> bool {
+        match &self.face_detector {
+            Some(face_detector) => !face_detector.detect_image(image).is_empty(),
+            None => true
+        }
+    }
+
+    /// Return true, no face detector being available.
+    #[cfg(not(feature = "face"))]
+    #[doc(hidden)]
+    fn has_face(&self, _image: &DynamicImage) -> bool {
+        true
+    }
+}
+
+#[doc(hidden)]
+fn invalid(err: image::ImageError) -> StarByFaceError {
+    StarByFaceError::InvalidImage(err.to_string())
+}
+
+/// Return the frame flattened on white with its delay.
+#[doc(hidden)]
+fn convert(frame: Frame) -> (DynamicImage, Duration) {
+    let (numerator, denominator) = frame.delay().numer_denom_ms();
+    let delay = Duration::from_secs_f64(numerator as f64 / denominator.max(1) as f64 / 1000.0);
+    (DynamicImage::ImageRgb8(flatten(&frame.into_buffer())), delay)
+}
+
+/// Return the image blended on a white background.
+#[doc(hidden)]
+fn flatten(image: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        image::Rgb([0, 1, 2].map(|channel| ((pixel[channel] as u32 * alpha + 255 * (255 - alpha)) / 255) as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+    use image::{Delay, DynamicImage, Frame, ImageFormat, Rgba, RgbaImage};
+    use image::codecs::gif::GifEncoder;
+    use image::codecs::webp::WebPEncoder;
+    use crate::{Client, StarByFaceError};
+    use super::{Animation, FrameSelection};
+
+    /// Return a checkerboard frame if `sharp`, else a uniform one.
+    fn frame(sharp: bool) -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |x, y| if sharp && (x + y) % 2 == 0 {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([200, 200, 200, 255])
+        })
+    }
+
+    /// Return frames shown for 100 ms times their position, the
+    /// one at `sharp` being the sharpest.
+    fn frames(count: usize, sharp: usize) -> Vec<RgbaImage> {
+        (0..count).map(|index| frame(index == sharp)).collect()
+    }
+
+    fn gif(frames: Vec<RgbaImage>) -> Vec<u8> {
+        let mut data = vec![];
+        GifEncoder::new(&mut data).encode_frames(frames.into_iter().enumerate().map(|(index, buffer)| {
+            Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(100 * (index as u32 + 1), 1))
+        })).unwrap();
+        data
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    /// Return the IHDR data and the compressed pixels of a frame.
+    fn png_parts(buffer: RgbaImage) -> (Vec<u8>, Vec<u8>) {
+        let mut png = vec![];
+        DynamicImage::ImageRgba8(buffer).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        let (mut header, mut pixels) = (vec![], vec![]);
+        let mut pos = 8;
+        while pos < png.len() {
+            let length = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let data = &png[pos + 8..pos + 8 + length];
+            match &png[pos + 4..pos + 8] {
+                b"IHDR" => header = data.to_vec(),
+                b"IDAT" => pixels.extend_from_slice(data),
+                _ => {}
+            }
+            pos += 12 + length;
+        }
+        (header, pixels)
+    }
+
+    fn apng(frames: Vec<RgbaImage>) -> Vec<u8> {
+        let count = frames.len() as u32;
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut sequence = 0u32;
+        for (index, buffer) in frames.into_iter().enumerate() {
+            let (header, pixels) = png_parts(buffer);
+            if index == 0 {
+                data.extend(png_chunk(b"IHDR", &header));
+                data.extend(png_chunk(b"acTL", &[count.to_be_bytes(), 0u32.to_be_bytes()].concat()));
+            }
+            let mut control = sequence.to_be_bytes().to_vec();
+            control.extend_from_slice(&header[..8]);
+            control.extend_from_slice(&[0; 8]);
+            control.extend_from_slice(&(100 * (index as u16 + 1)).to_be_bytes());
+            control.extend_from_slice(&1000u16.to_be_bytes());
+            control.extend_from_slice(&[0, 0]);
+            data.extend(png_chunk(b"fcTL", &control));
+            sequence += 1;
+            if index == 0 {
+                data.extend(png_chunk(b"IDAT", &pixels));
+            } else {
+                data.extend(png_chunk(b"fdAT", &[&sequence.to_be_bytes()[..], &pixels].concat()));
+                sequence += 1;
+            }
+        }
+        data.extend(png_chunk(b"IEND", b""));
+        data
+    }
+
+    fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn webp(frames: Vec<RgbaImage>) -> Vec<u8> {
+        let mut chunks = webp_chunk(b"VP8X", &[0x02, 0, 0, 0, 7, 0, 0, 7, 0, 0]);
+        chunks.extend(webp_chunk(b"ANIM", &[255, 255, 255, 255, 0, 0]));
+        for (index, buffer) in frames.into_iter().enumerate() {
+            let mut still = vec![];
+            WebPEncoder::new_lossless(&mut still).encode(buffer.as_raw(), 8, 8, image::ExtendedColorType::Rgba8).unwrap();
+            let duration = (100 * (index as u32 + 1)).to_le_bytes();
+            let mut frame = vec![0, 0, 0, 0, 0, 0, 7, 0, 0, 7, 0, 0, duration[0], duration[1], duration[2], 0];
+            frame.extend_from_slice(&still[12..]);
+            chunks.extend(webp_chunk(b"ANMF", &frame));
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend(chunks);
+        data
+    }
+
+    fn select(animation: Animation, data: &[u8]) -> (usize, Vec<(usize, Duration)>) {
+        let (frame_count, frames) = animation.select(data).unwrap();
+        (frame_count, frames.into_iter().map(|(index, _, delay)| (index, delay)).collect())
+    }
+
+    fn animation(selection: FrameSelection) -> Animation {
+        Animation::new(&Client::new()).with_selection(selection)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn samples_frames() {
+        for data in [gif(frames(5, 0)), apng(frames(5, 0)), webp(frames(5, 0))] {
+            assert_eq!(select(animation(FrameSelection::Sample(2)), &data), (5, vec![(0, ms(100)), (2, ms(300))]));
+            assert_eq!(select(animation(FrameSelection::Sample(9)), &data).1.len(), 5);
+        }
+    }
+
+    #[test]
+    fn selects_the_sharpest_frame() {
+        for data in [gif(frames(4, 2)), apng(frames(4, 2)), webp(frames(4, 2))] {
+            assert_eq!(select(animation(FrameSelection::Sharpest), &data), (4, vec![(2, ms(300))]));
+        }
+    }
+
+    #[test]
+    fn ignores_frames_past_the_maximum() {
+        for data in [gif(frames(6, 5)), apng(frames(6, 5)), webp(frames(6, 5))] {
+            let animation = animation(FrameSelection::Sample(3)).with_max_frames(3);
+            assert_eq!(select(animation.clone(), &data), (3, vec![(0, ms(100)), (1, ms(200)), (2, ms(300))]));
+            assert_eq!(select(animation.with_selection(FrameSelection::Sharpest), &data), (3, vec![(0, ms(100))]));
+        }
+    }
+
+    #[test]
+    fn rejects_large_animations() {
+        for data in [gif(frames(2, 0)), apng(frames(2, 0)), webp(frames(2, 0))] {
+            match animation(FrameSelection::Sample(2)).with_max_pixels(63).select(&data) {
+                Err(StarByFaceError::InvalidImage(message)) => assert!(message.contains("8x8"), "{}", message),
+                result => panic!("{:?}", result.map(|(frame_count, _)| frame_count))
+            }
+            assert_eq!(select(animation(FrameSelection::Sample(2)).with_max_pixels(64), &data).0, 2);
+        }
+    }
+
+    #[test]
+    fn selects_no_frame_of_still_images() {
+        let mut png = vec![];
+        DynamicImage::ImageRgba8(frame(true)).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        for selection in [FrameSelection::Sample(2), FrameSelection::Sharpest] {
+            assert_eq!(select(animation(selection), &gif(frames(1, 0))), (1, vec![]));
+            assert_eq!(select(animation(selection), &png), (0, vec![]));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use crate::{Client, Lookup, Source};
+
+/// Looks up several images concurrently.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl Batch {
+
+    /// `client` Client used to send the requests.
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            concurrency: 4
+        }
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `sources` Images to look up.
+    ///
+    /// Return lookups in the order of `sources`.
+    pub fn run(&self, sources: &[Source]) -> Vec<Lookup> {
+        self.run_with_progress(sources, |_, _| {})
+    }
+
+    /// `sources` Images to look up.
+    ///
+    /// `progress` Called with the index of each source
+    /// and its lookup as soon as it completes.
+    ///
+    /// Return lookups in the order of `sources`.
+    pub fn run_with_progress<F>(&self, sources: &[Source], progress: F) -> Vec<Lookup>
+        where F: Fn(usize, &Lookup) + Sync {
+        let next = AtomicUsize::new(0);
+        let lookups: Mutex<Vec<Option<Lookup>>> = Mutex::new(vec![None; sources.len()]);
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(sources.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let source = match sources.get(index) {
+                        Some(source) => source,
+                        None => break
+                    };
+                    let lookup = Lookup::new(source, &self.client);
+                    progress(index, &lookup);
+                    match lookups.lock() {
+                        Ok(mut lookups) => lookups[index] = Some(lookup),
+                        Err(poisoned) => poisoned.into_inner()[index] = Some(lookup)
+                    }
+                });
+            }
+        });
+        let lookups = match lookups.into_inner() {
+            Ok(lookups) => lookups,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        lookups.into_iter().flatten().collect()
+    }
+}
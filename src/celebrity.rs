@@ -0,0 +1,88 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::BTreeMap;
+use json::JsonValue;
+use crate::Gender;
+
+/// Celebrity card of a result page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Celebrity {
+    /// Position within its gender section, starting at 1.
+    pub rank: usize,
+    /// Similarity in percent.
+    pub similarity: f64,
+    pub name: String,
+    pub gender: Gender,
+    pub image: String,
+    pub wiki: String,
+    /// Attributes of the card element.
+    pub attributes: BTreeMap<String, String>,
+    /// Links of the card other than `wiki`.
+    pub links: Vec<String>
+}
+
+impl Celebrity {
+
+    /// Return JSON Object.
+    pub fn to_json(&self) -> std::string::String {
+        let mut json = JsonValue::new_object();
+        json["similarity"] = match self.attributes.get("similarity") {
+            Some(similarity) => format!("{}%",similarity.trim().trim_end_matches('%')),
+            None => format!("{}%",self.similarity)
+        }.into();
+        json["name"] = self.name.as_str().into();
+        json["gender"] = self.gender.to_string().into();
+        json["image"] = self.image.as_str().into();
+        json["wiki"] = self.wiki.as_str().into();
+        json["rank"] = self.rank.into();
+        let mut attributes = JsonValue::new_object();
+        for (key, value) in &self.attributes {
+            attributes[key.as_str()] = value.as_str().into();
+        }
+        json["attributes"] = attributes;
+        json["links"] = self.links.iter().map(|link| link.as_str()).collect::<Vec<_>>().into();
+        json.to_string()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::collections::BTreeMap;
+    use crate::Gender;
+    use super::Celebrity;
+
+    /// Return a card of `name` at `rank` with `similarity` percent.
+    pub(crate) fn card(name: &str, rank: usize, similarity: f64) -> Celebrity {
+        Celebrity {
+            rank,
+            similarity,
+            name: name.to_string(),
+            gender: Gender::FEMALE,
+            image: String::new(),
+            wiki: format!("https://en.wikipedia.org/wiki/{}", name),
+            attributes: BTreeMap::new(),
+            links: vec![]
+        }
+    }
+
+    /// `similarities` Names and similarities, ranked in order.
+    pub(crate) fn cards(similarities: &[(&str, f64)]) -> Vec<Celebrity> {
+        similarities.iter()
+            .enumerate()
+            .map(|(index, &(name, similarity))| card(name, index + 1, similarity))
+            .collect()
+    }
+}
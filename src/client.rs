@@ -0,0 +1,629 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use reqwest::blocking::{multipart, RequestBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use crate::{strip_metadata, Preprocessing, RateLimiter, Source, StarByFaceError};
+#[cfg(feature = "image")]
+use crate::{Preprocessor, QualityAnalyzer};
+#[cfg(feature = "face")]
+use crate::{Aligner, FaceDetector};
+use crate::coalesce::Coalescer;
+use crate::page::{has_results, Page};
+use crate::source::{mime_type, sha256};
+#[cfg(feature = "image")]
+use crate::source::jpeg_file_name;
+use crate::telemetry;
+
+#[doc(hidden)]
+const BASE_URL: &str = "https://starbyface.com";
+
+#[doc(hidden)]
+const SNIPPET_LENGTH: usize = 512;
+
+#[doc(hidden)]
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+#[doc(hidden)]
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+#[doc(hidden)]
+const CHALLENGE_MARKERS: [&str; 5] = [
+    "cf-chl",
+    "challenge-platform",
+    "cf_chl_opt",
+    "<title>Just a moment...</title>",
+    "<title>Attention Required! | Cloudflare</title>"
+];
+
+#[doc(hidden)]
+static SHARED: RwLock<Option<Client>> = RwLock::new(None);
+
+/// HTTP client used to reach StarByFace.
+///
+/// Clones share the connection pool and the rate limiter.
+/// Concurrent lookups of the same image through clones of
+/// one client share a single upstream request, unless the
+/// clone was given another base URL or dump directory.
+#[derive(Clone, Debug)]
+pub struct Client {
+    #[doc(hidden)]
+    http: reqwest::blocking::Client,
+    #[doc(hidden)]
+    base_url: String,
+    #[doc(hidden)]
+    rate_limiter: Option<RateLimiter>,
+    #[doc(hidden)]
+    retries: u32,
+    #[doc(hidden)]
+    dump_dir: Option<PathBuf>,
+    #[doc(hidden)]
+    redact: bool,
+    #[doc(hidden)]
+    strip_metadata: bool,
+    #[cfg(feature = "image")]
+    #[doc(hidden)]
+    preprocessor: Option<Preprocessor>,
+    #[cfg(feature = "image")]
+    #[doc(hidden)]
+    quality_analyzer: Option<QualityAnalyzer>,
+    #[cfg(feature = "face")]
+    #[doc(hidden)]
+    face_detector: Option<FaceDetector>,
+    #[cfg(feature = "face")]
+    #[doc(hidden)]
+    aligner: Option<Aligner>,
+    #[doc(hidden)]
+    inflight: Arc<Coalescer<Result<Page, StarByFaceError>>>
+}
+
+/// Image sent to StarByFace, files having been read.
+#[doc(hidden)]
+enum Upload {
+    Url(String),
+    Bytes {
+        data: Vec<u8>,
+        file_name: String
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            http: reqwest::blocking::Client::new(),
+            base_url: BASE_URL.to_string(),
+            rate_limiter: None,
+            retries: 0,
+            dump_dir: None,
+            redact: true,
+            strip_metadata: true,
+            #[cfg(feature = "image")]
+            preprocessor: None,
+            #[cfg(feature = "image")]
+            quality_analyzer: None,
+            #[cfg(feature = "face")]
+            face_detector: None,
+            #[cfg(feature = "face")]
+            aligner: None,
+            inflight: Arc::new(Coalescer::new())
+        }
+    }
+}
+
+impl Client {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `base_url` Upstream base URL, `https://starbyface.com` by default.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self.inflight = Arc::new(Coalescer::new());
+        self
+    }
+
+    /// `rate_limiter` Limiter applied before every request.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// `retries` Number of times a request is sent again after a
+    /// transport error or a 429 or 5xx status, 0 by default.
+    ///
+    /// Retries wait for `Retry-After`, up to 30 seconds, or back
+    /// off from 250 milliseconds.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// `dump_dir` Directory the raw body of responses whose
+    /// markup changed is written to.
+    pub fn with_dump_dir<P: AsRef<Path>>(mut self, dump_dir: P) -> Self {
+        self.dump_dir = Some(dump_dir.as_ref().to_path_buf());
+        self.inflight = Arc::new(Coalescer::new());
+        self
+    }
+
+    /// `full_urls` Record full image and upstream URLs in
+    /// `tracing` spans instead of their host only, false by default.
+    pub fn with_full_urls_in_traces(mut self, full_urls: bool) -> Self {
+        self.redact = !full_urls;
+        self
+    }
+
+    /// `strip_metadata` Remove EXIF, XMP and IPTC metadata from
+    /// files and bytes before they are uploaded, true by default.
+    pub fn with_metadata_stripping(mut self, strip_metadata: bool) -> Self {
+        self.strip_metadata = strip_metadata;
+        self
+    }
+
+    /// `preprocessor` Preprocessing applied to files and bytes
+    /// before they are uploaded.
+    #[cfg(feature = "image")]
+    pub fn with_preprocessor(mut self, preprocessor: Preprocessor) -> Self {
+        self.preprocessor = Some(preprocessor);
+        self
+    }
+
+    /// `quality_analyzer` Analyzer scoring files and bytes before
+    /// they are uploaded.
+    #[cfg(feature = "image")]
+    pub fn with_quality_analyzer(mut self, quality_analyzer: QualityAnalyzer) -> Self {
+        self.quality_analyzer = Some(quality_analyzer);
+        self
+    }
+
+    /// `face_detector` Detector run on files and bytes before
+    /// they are uploaded.
+    #[cfg(feature = "face")]
+    pub fn with_face_detector(mut self, face_detector: FaceDetector) -> Self {
+        self.face_detector = Some(face_detector);
+        self
+    }
+
+    /// `aligner` Aligner cropping files and bytes to their face
+    /// before they are uploaded.
+    #[cfg(feature = "face")]
+    pub fn with_aligner(mut self, aligner: Aligner) -> Self {
+        self.aligner = Some(aligner);
+        self
+    }
+
+    /// Return a clone of the client without face detector.
+    #[cfg(feature = "face")]
+    #[doc(hidden)]
+    pub(crate) fn without_face_detector(&self) -> Self {
+        Self {
+            face_detector: None,
+            ..self.clone()
+        }
+    }
+
+    /// Return the client used by `StarByFace::new` and
+    /// `StarByFaceFile::new`.
+    pub fn shared() -> Self {
+        if let Ok(shared) = SHARED.read() {
+            if let Some(client) = shared.as_ref() {
+                return client.clone()
+            }
+        }
+        match SHARED.write() {
+            Ok(mut shared) => shared.get_or_insert_with(Client::new).clone(),
+            Err(_) => Client::new()
+        }
+    }
+
+    /// `client` Client used by every lookup of this process
+    /// that doesn't specify one.
+    pub fn set_shared(client: Client) {
+        match SHARED.write() {
+            Ok(mut shared) => *shared = Some(client),
+            Err(poisoned) => *poisoned.into_inner() = Some(client)
+        }
+    }
+
+    #[doc(hidden)]
+    fn redact_source(&self, source: &Source) -> String {
+        match *source {
+            Source::Url(ref image_uri) => self.redact(image_uri),
+            Source::File(ref image_file) => if self.redact {
+                "[redacted]".to_string()
+            } else {
+                image_file.display().to_string()
+            },
+            Source::Bytes { ref file_name, .. } => if self.redact {
+                "[redacted]".to_string()
+            } else {
+                file_name.to_string()
+            }
+        }
+    }
+
+    /// Return the scheme and host of `url` only, unless
+    /// redaction is disabled.
+    #[doc(hidden)]
+    fn redact(&self, url: &str) -> String {
+        if !self.redact {
+            return url.to_string()
+        }
+        match reqwest::Url::parse(url) {
+            Ok(url) => match url.host_str() {
+                Some(host) => match url.port() {
+                    Some(port) => format!("{}://{}:{}/[redacted]", url.scheme(), host, port),
+                    None => format!("{}://{}/[redacted]", url.scheme(), host)
+                },
+                None => "[redacted]".to_string()
+            },
+            Err(_) => "[redacted]".to_string()
+        }
+    }
+
+    /// Read files, strip metadata, preprocess the image to
+    /// upload, score its quality, count its faces and align it.
+    #[doc(hidden)]
+    fn prepare(&self, source: &Source) -> Result<(Upload, Option<Preprocessing>), StarByFaceError> {
+        if let Source::Url(ref image_uri) = *source {
+            return Ok((Upload::Url(image_uri.clone()), None))
+        }
+        #[cfg_attr(not(feature = "image"), allow(unused_mut))]
+        let (mut data, mut file_name) = source.read()?;
+        let mut preprocessing = None;
+        if self.strip_metadata {
+            match strip_metadata(&data) {
+                Ok((stripped, removed)) => {
+                    preprocessing = Some(Preprocessing {
+                        length: stripped.len(),
+                        metadata_removed: removed,
+                        ..Preprocessing::of(&data)
+                    });
+                    data = stripped;
+                },
+                Err(err) => return Err(err)
+            }
+        }
+        #[cfg(feature = "image")]
+        if let Some(preprocessor) = &self.preprocessor {
+            let (processed, mut report) = preprocessor.process(&data)?;
+            if let Some(stripped) = preprocessing {
+                report.original_length = stripped.original_length;
+                report.metadata_removed = stripped.metadata_removed;
+            }
+            if report.converted.is_some() {
+                file_name = jpeg_file_name(&file_name);
+            }
+            data = processed;
+            preprocessing = Some(report);
+        }
+        #[cfg(feature = "image")]
+        if let Some(quality_analyzer) = &self.quality_analyzer {
+            let quality = quality_analyzer.check(&data)?;
+            preprocessing.get_or_insert_with(|| Preprocessing::of(&data)).quality = Some(quality);
+        }
+        #[cfg(feature = "face")]
+        if let Some(face_detector) = &self.face_detector {
+            let faces = face_detector.check(&data)?;
+            preprocessing.get_or_insert_with(|| Preprocessing::of(&data)).faces = Some(faces);
+        }
+        #[cfg(feature = "face")]
+        if let Some(aligner) = &self.aligner {
+            match aligner.align(&data)? {
+                Some((aligned, alignment)) => {
+                    let report = preprocessing.get_or_insert_with(|| Preprocessing::of(&data));
+                    report.alignment = Some(alignment);
+                    report.length = aligned.len();
+                    data = aligned;
+                    file_name = jpeg_file_name(&file_name);
+                },
+                None => tracing::warn!("no face to align")
+            }
+        }
+        Ok((Upload::Bytes { data, file_name }, preprocessing))
+    }
+
+    #[doc(hidden)]
+    pub(crate) fn lookup(&self, source: &Source) -> (Result<Page, StarByFaceError>, Option<Preprocessing>) {
+        let kind = source.kind();
+        let span = tracing::info_span!("lookup",
+            source = kind,
+            image = %self.redact_source(source),
+            image_hash = Empty,
+            preprocessing = Empty,
+            upstream = Empty,
+            shared = Empty,
+            status = Empty,
+            bytes = Empty,
+            parse_ms = Empty,
+            cards = Empty,
+            warning = Empty);
+        let _enter = span.enter();
+        let (upload, preprocessing) = match self.prepare(source) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to prepare image");
+                let result = Err(err);
+                telemetry::lookup(kind, &result, false);
+                return (result, None)
+            }
+        };
+        if let Some(preprocessing) = preprocessing.as_ref().filter(|preprocessing| preprocessing.is_changed()) {
+            span.record("preprocessing", tracing::field::display(preprocessing));
+        }
+        let (upload_kind, hash) = match upload {
+            Upload::Url(ref image_uri) => ("url", sha256(image_uri.as_bytes())),
+            Upload::Bytes { ref data, .. } => ("bytes", sha256(data))
+        };
+        span.record("image_hash", hash.as_str());
+        let fallback = Err(StarByFaceError::Error("Coalesced lookup panicked".to_string()));
+        let (result, shared) = self.inflight.run(&format!("{}:{}", upload_kind, hash), fallback, || {
+            self.fetch(kind, &upload).map(|body| {
+                let start = Instant::now();
+                let page = Page::parse(body).dump(self.dump_dir.as_deref());
+                span.record("parse_ms", start.elapsed().as_secs_f64() * 1000.0);
+                page
+            })
+        });
+        span.record("shared", shared);
+        match result {
+            Ok(ref page) => {
+                span.record("cards", page.diagnostics().cards);
+                if let Some(warning) = page.warning() {
+                    span.record("warning", warning.as_str());
+                }
+                tracing::info!(outcome = page.outcome(), "lookup completed")
+            },
+            Err(ref err) => tracing::warn!(outcome = err.kind(), error = %err, "lookup failed")
+        }
+        telemetry::lookup(kind, &result, shared);
+        (result, preprocessing)
+    }
+
+    #[doc(hidden)]
+    fn fetch(&self, kind: &'static str, upload: &Upload) -> Result<String, StarByFaceError> {
+        match *upload {
+            Upload::Url(ref image_uri) => self.send(kind, "/Home/LooksLike", |url| Ok(self.http.post(url)
+                .header(CONTENT_TYPE,"application/json; utf-8")
+                .body(json::object!{ "url": image_uri.as_str() }.dump()))),
+            Upload::Bytes { ref data, ref file_name } => self.send(kind, "/Home/LooksLikeByPhoto", |url| match multipart::Part::bytes(data.clone())
+                .file_name(file_name.clone())
+                .mime_str(mime_type(data)) {
+                Ok(part) => Ok(self.http.post(url).multipart(multipart::Form::new().part("image", part))),
+                Err(err) => Err(StarByFaceError::Error(err.to_string()))
+            })
+        }
+    }
+
+    /// Send the request built by `request`, again on transport
+    /// errors and 429 or 5xx statuses as long as retries are left.
+    #[doc(hidden)]
+    fn send<F>(&self, kind: &'static str, path: &str, request: F) -> Result<String, StarByFaceError>
+        where F: Fn(String) -> Result<RequestBuilder, StarByFaceError> {
+        let url = format!("{}{}", self.base_url, path);
+        let span = tracing::Span::current();
+        span.record("upstream", self.redact(&url).as_str());
+        let mut attempt = 0;
+        let response = loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                if let Err(err) = rate_limiter.acquire() {
+                    return Err(StarByFaceError::Error(err.to_string()))
+                }
+            }
+            let start = Instant::now();
+            let response = request(url.clone())?.send();
+            telemetry::upstream(kind, start.elapsed());
+            let (reason, wait) = match retry(&response, attempt) {
+                Some(retry) if attempt < self.retries => retry,
+                _ => match response {
+                    Ok(response) => break response,
+                    Err(err) => return Err(request_error(err))
+                }
+            };
+            attempt += 1;
+            tracing::debug!(attempt, reason = reason.as_str(), wait_ms = wait.as_millis() as u64, "retrying request");
+            telemetry::retry(kind, &reason);
+            thread::sleep(wait);
+        };
+        let status = response.status();
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let challenged = response.headers().get("cf-mitigated")
+            .map(|value| value.as_bytes() == b"challenge")
+            .unwrap_or(false);
+        let body = match response.text() {
+            Ok(body) => body,
+            Err(err) => return Err(request_error(err))
+        };
+        span.record("status", status.as_u16());
+        span.record("bytes", body.len());
+        validate(status.as_u16(), &content_type, challenged, body)
+    }
+}
+
+/// Return the body of a response, or an error if it isn't a page
+/// of StarByFace.
+///
+/// Cloudflare adds its challenge scripts to normal pages too, so a
+/// challenge needs `cf-mitigated: challenge`, or a 403 or 503 status
+/// with a challenge marker, and a body without results.
+#[doc(hidden)]
+fn validate(status: u16, content_type: &str, challenged: bool, body: String) -> Result<String, StarByFaceError> {
+    let blocked = challenged || (matches!(status, 403 | 503) && CHALLENGE_MARKERS.iter().any(|marker| body.contains(marker)));
+    if blocked && !has_results(&body) {
+        return Err(StarByFaceError::Challenge { status, body: snippet(&body) })
+    }
+    if !(200..300).contains(&status) && !body.contains("\"errorMsg\":") {
+        return Err(StarByFaceError::Status { status, body: snippet(&body) })
+    }
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    if !mime.is_empty() && !mime.starts_with("text/") && mime != "application/json" {
+        return Err(StarByFaceError::ContentType { status, content_type: content_type.to_string(), body: snippet(&body) })
+    }
+    if body.trim().is_empty() {
+        return Err(StarByFaceError::EmptyBody { status })
+    }
+    Ok(body)
+}
+
+/// Return the reason to send a request again and how long to
+/// wait, `None` unless it failed to send or got a 429 or 5xx
+/// status without a challenge.
+///
+/// The wait is the `Retry-After` of the response, up to 30 seconds,
+/// or doubles from 250 milliseconds with each attempt.
+#[doc(hidden)]
+fn retry(response: &reqwest::Result<Response>, attempt: u32) -> Option<(String, Duration)> {
+    let backoff = RETRY_BACKOFF * 2u32.pow(attempt.min(6));
+    let response = match response {
+        Ok(response) => response,
+        Err(_) => return Some(("error".to_string(), backoff))
+    };
+    let status = response.status().as_u16();
+    if !(status == 429 || (500..600).contains(&status)) || response.headers().contains_key("cf-mitigated") {
+        return None
+    }
+    let retry_after = response.headers().get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    match retry_after {
+        Some(seconds) => Some((status.to_string(), Duration::from_secs(seconds).min(MAX_RETRY_AFTER))),
+        None => Some((status.to_string(), backoff))
+    }
+}
+
+/// Return the error of a request with its causes but without
+/// its URL, which may hold credentials or the image address.
+#[doc(hidden)]
+fn request_error(err: reqwest::Error) -> StarByFaceError {
+    let err = err.without_url();
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    StarByFaceError::Error(message)
+}
+
+#[doc(hidden)]
+fn snippet(body: &str) -> String {
+    match body.char_indices().nth(SNIPPET_LENGTH) {
+        Some((index, _)) => format!("{}...", &body[..index]),
+        None => body.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StarByFaceError;
+    use super::validate;
+
+    const RESULTS: &str = r#"<div id="female-celebs-result"><div class="card" similarity="90%">"#;
+
+    const JSD: &str = r#"<script src="/cdn-cgi/challenge-platform/scripts/jsd/main.js"></script>"#;
+
+    const CHALLENGE: &str = "<html><head><title>Just a moment...</title></head><body><script>window._cf_chl_opt={}</script></body></html>";
+
+    #[test]
+    fn results_page_is_returned() {
+        assert_eq!(validate(200, "text/html; charset=utf-8", false, RESULTS.to_string()).unwrap(), RESULTS);
+    }
+
+    #[test]
+    fn results_page_with_jsd_script_is_not_a_challenge() {
+        let body = format!("{}{}", RESULTS, JSD);
+        assert_eq!(validate(200, "text/html", false, body.clone()).unwrap(), body);
+    }
+
+    #[test]
+    fn page_with_jsd_script_is_not_a_challenge() {
+        let body = format!("<html><script>showError(\"No face\");</script>{}</html>", JSD);
+        assert!(validate(200, "text/html", false, body).is_ok());
+    }
+
+    #[test]
+    fn challenge_needs_a_blocking_status() {
+        assert!(validate(200, "text/html", false, CHALLENGE.to_string()).is_ok());
+        for status in [403, 503] {
+            match validate(status, "text/html", false, CHALLENGE.to_string()) {
+                Err(StarByFaceError::Challenge { status: found, .. }) => assert_eq!(found, status),
+                result => panic!("{:?}", result)
+            }
+        }
+    }
+
+    #[test]
+    fn challenge_header_is_a_challenge() {
+        match validate(200, "text/html", true, String::from("<html></html>")) {
+            Err(StarByFaceError::Challenge { status: 200, .. }) => {},
+            result => panic!("{:?}", result)
+        }
+    }
+
+    #[test]
+    fn results_are_never_a_challenge() {
+        let body = format!("{}{}", RESULTS, CHALLENGE);
+        assert!(validate(503, "text/html", true, body).is_err_and(|err| !matches!(err, StarByFaceError::Challenge { .. })));
+    }
+
+    #[test]
+    fn error_status_is_rejected() {
+        match validate(500, "text/html", false, String::from("Internal Server Error")) {
+            Err(StarByFaceError::Status { status: 500, body }) => assert_eq!(body, "Internal Server Error"),
+            result => panic!("{:?}", result)
+        }
+        match validate(403, "text/html", false, String::from("Forbidden")) {
+            Err(StarByFaceError::Status { status: 403, .. }) => {},
+            result => panic!("{:?}", result)
+        }
+    }
+
+    #[test]
+    fn error_status_with_error_message_is_returned() {
+        let body = String::from(r#"{"errorMsg":"Parameter is not valid"}"#);
+        assert_eq!(validate(400, "application/json", false, body.clone()).unwrap(), body);
+    }
+
+    #[test]
+    fn content_type_is_checked() {
+        assert!(validate(200, "text/plain", false, RESULTS.to_string()).is_ok());
+        assert!(validate(200, "application/json; charset=utf-8", false, RESULTS.to_string()).is_ok());
+        assert!(validate(200, "", false, RESULTS.to_string()).is_ok());
+        match validate(200, "image/png", false, RESULTS.to_string()) {
+            Err(StarByFaceError::ContentType { status: 200, content_type, .. }) => assert_eq!(content_type, "image/png"),
+            result => panic!("{:?}", result)
+        }
+    }
+
+    #[test]
+    fn empty_body_is_rejected() {
+        for body in ["", "  \n\t"] {
+            match validate(200, "text/html", false, body.to_string()) {
+                Err(StarByFaceError::EmptyBody { status: 200 }) => {},
+                result => panic!("{:?}", result)
+            }
+        }
+    }
+}
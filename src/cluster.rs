@@ -0,0 +1,309 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::HashMap;
+use crate::{Batch, Celebrity, Client, ConsensusCelebrity, Lookup, Source, StarByFaceError};
+use crate::consensus::{aggregate, key};
+
+/// Similarity of two clusters from the similarities of their photos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Linkage {
+    /// Highest similarity of two photos.
+    Single,
+    /// Lowest similarity of two photos.
+    Complete,
+    /// Mean similarity of the photos, the default.
+    Average
+}
+
+/// Photos grouped by a `Clustering`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// Indices of the photos in the results or sources.
+    pub photos: Vec<usize>,
+    /// Celebrities representing the cluster, best first.
+    pub celebrities: Vec<ConsensusCelebrity>
+}
+
+/// Result of `Clustering::run`.
+#[derive(Debug, Clone)]
+pub struct ClusteringResult {
+    /// Clusters, largest first.
+    pub clusters: Vec<Cluster>,
+    /// Cluster of each photo, `None` if its lookup failed.
+    pub assignments: Vec<Option<usize>>,
+    /// Lookup of each photo, in the order of the sources.
+    pub lookups: Vec<Lookup>
+}
+
+/// Groups photos that share their lookalikes.
+///
+/// Each photo is a vector of the similarities of its
+/// celebrities, matched by wiki link, and photos are merged
+/// agglomeratively while the cosine similarity of their
+/// clusters reaches the threshold.
+#[derive(Debug, Clone)]
+pub struct Clustering {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    threshold: f64,
+    #[doc(hidden)]
+    linkage: Linkage,
+    #[doc(hidden)]
+    representatives: usize,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl Clustering {
+
+    /// `client` Client used to send the requests.
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            threshold: 0.5,
+            linkage: Linkage::Average,
+            representatives: 3,
+            concurrency: 4
+        }
+    }
+
+    /// `threshold` Lowest cosine similarity of two clusters
+    /// merged, from 0 to 1, 0.5 by default.
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// `linkage` Similarity of two clusters, `Linkage::Average`
+    /// by default.
+    pub fn with_linkage(mut self, linkage: Linkage) -> Self {
+        self.linkage = linkage;
+        self
+    }
+
+    /// `representatives` Number of celebrities of each cluster,
+    /// 3 by default.
+    pub fn with_representatives(mut self, representatives: usize) -> Self {
+        self.representatives = representatives;
+        self
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `sources` Photos to group.
+    ///
+    /// Return the clusters of the photos that were looked up,
+    /// or the error of the first photo if none succeeded.
+    pub fn run(&self, sources: &[Source]) -> Result<ClusteringResult, StarByFaceError> {
+        let lookups = Batch::new(&self.client).with_concurrency(self.concurrency).run(sources);
+        let results: Vec<(usize, Vec<Celebrity>)> = lookups.iter()
+            .enumerate()
+            .filter_map(|(index, lookup)| lookup.celebrities().ok().map(|celebrities| (index, celebrities)))
+            .collect();
+        if results.is_empty() {
+            return match lookups.first().map(|lookup| lookup.celebrities()) {
+                Some(Err(err)) => Err(err),
+                _ => Err(StarByFaceError::Null(String::from("No photo to look up")))
+            }
+        }
+        let mut clusters = self.cluster(&results.iter().map(|(_, celebrities)| celebrities.clone()).collect::<Vec<_>>());
+        let mut assignments = vec![None; lookups.len()];
+        for (cluster_index, cluster) in clusters.iter_mut().enumerate() {
+            for photo in cluster.photos.iter_mut() {
+                *photo = results[*photo].0;
+                assignments[*photo] = Some(cluster_index);
+            }
+            for celebrity in cluster.celebrities.iter_mut() {
+                for provenance in celebrity.photos.iter_mut() {
+                    provenance.photo = results[provenance.photo].0;
+                }
+            }
+        }
+        tracing::info!(photos = lookups.len(), succeeded = results.len(), clusters = clusters.len(), "clustered photos");
+        Ok(ClusteringResult { clusters, assignments, lookups })
+    }
+
+    /// `results` Celebrities of each photo.
+    ///
+    /// Return the clusters of the photos, largest first.
+    pub fn cluster(&self, results: &[Vec<Celebrity>]) -> Vec<Cluster> {
+        let vectors: Vec<HashMap<&str, f64>> = results.iter().map(|celebrities| vector(celebrities)).collect();
+        let count = vectors.len();
+        let mut similarity = vec![vec![0.0; count]; count];
+        for i in 0..count {
+            for j in i + 1..count {
+                similarity[i][j] = cosine(&vectors[i], &vectors[j]);
+                similarity[j][i] = similarity[i][j];
+            }
+        }
+        let mut members: Vec<Vec<usize>> = (0..count).map(|i| vec![i]).collect();
+        let mut active = vec![true; count];
+        let mut nearest: Vec<Option<usize>> = (0..count).map(|i| nearest_active(&similarity, &active, i)).collect();
+        loop {
+            let closest = (0..count)
+                .filter(|&i| active[i])
+                .filter_map(|i| nearest[i].map(|j| (i, j)))
+                .max_by(|&(a, b), &(c, d)| similarity[a][b].total_cmp(&similarity[c][d]));
+            let (i, j) = match closest {
+                Some((i, j)) if similarity[i][j] >= self.threshold => (i, j),
+                _ => break
+            };
+            let (size_i, size_j) = (members[i].len() as f64, members[j].len() as f64);
+            for k in 0..count {
+                if active[k] && k != i && k != j {
+                    let merged = match self.linkage {
+                        Linkage::Single => similarity[i][k].max(similarity[j][k]),
+                        Linkage::Complete => similarity[i][k].min(similarity[j][k]),
+                        Linkage::Average => (similarity[i][k] * size_i + similarity[j][k] * size_j) / (size_i + size_j)
+                    };
+                    similarity[i][k] = merged;
+                    similarity[k][i] = merged;
+                }
+            }
+            let moved = std::mem::take(&mut members[j]);
+            members[i].extend(moved);
+            active[j] = false;
+            for k in 0..count {
+                if !active[k] {
+                    continue
+                }
+                if k == i || nearest[k] == Some(i) || nearest[k] == Some(j) {
+                    nearest[k] = nearest_active(&similarity, &active, k);
+                } else if nearest[k].is_some_and(|n| similarity[k][i] > similarity[k][n]) {
+                    nearest[k] = Some(i);
+                }
+            }
+        }
+        let mut clusters: Vec<Cluster> = members.into_iter()
+            .filter(|photos| !photos.is_empty())
+            .map(|mut photos| {
+                photos.sort_unstable();
+                let mut celebrities = aggregate(&photos.iter().map(|&photo| (photo, results[photo].clone())).collect::<Vec<_>>());
+                celebrities.truncate(self.representatives);
+                Cluster { photos, celebrities }
+            })
+            .collect();
+        clusters.sort_by(|a, b| b.photos.len().cmp(&a.photos.len()).then(a.photos.cmp(&b.photos)));
+        clusters
+    }
+}
+
+/// Return the similarity of each celebrity of a photo by key,
+/// from 0 to 1.
+#[doc(hidden)]
+fn vector(celebrities: &[Celebrity]) -> HashMap<&str, f64> {
+    let mut vector: HashMap<&str, f64> = HashMap::new();
+    for celebrity in celebrities {
+        let value = vector.entry(key(celebrity)).or_insert(0.0);
+        *value = value.max(celebrity.similarity / 100.0);
+    }
+    vector
+}
+
+/// Return the cosine similarity of two vectors, 0 if either is empty.
+#[doc(hidden)]
+fn cosine(a: &HashMap<&str, f64>, b: &HashMap<&str, f64>) -> f64 {
+    let dot: f64 = a.iter().filter_map(|(key, x)| b.get(key).map(|y| x * y)).sum();
+    let norm_a = a.values().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.values().map(|y| y * y).sum::<f64>().sqrt();
+    if norm_a > 0.0 && norm_b > 0.0 {
+        dot / (norm_a * norm_b)
+    } else {
+        0.0
+    }
+}
+
+/// Return the most similar active cluster to `i`.
+#[doc(hidden)]
+fn nearest_active(similarity: &[Vec<f64>], active: &[bool], i: usize) -> Option<usize> {
+    (0..similarity.len())
+        .filter(|&j| j != i && active[j])
+        .max_by(|&a, &b| similarity[i][a].total_cmp(&similarity[i][b]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Celebrity, Client};
+    use crate::celebrity::test_support::cards;
+    use super::{Clustering, Linkage};
+
+    /// Return photos whose cosine similarities are 0.743 for the
+    /// first two, 0.669 for the last two and 0 for the first and last.
+    fn chain() -> Vec<Vec<Celebrity>> {
+        vec![
+            cards(&[("Ann", 100.0)]),
+            cards(&[("Ann", 100.0), ("Bea", 90.0)]),
+            cards(&[("Bea", 100.0)])
+        ]
+    }
+
+    fn clusters(threshold: f64, linkage: Linkage) -> Vec<Vec<usize>> {
+        Clustering::new(&Client::new())
+            .with_threshold(threshold)
+            .with_linkage(linkage)
+            .cluster(&chain())
+            .into_iter()
+            .map(|cluster| cluster.photos)
+            .collect()
+    }
+
+    #[test]
+    fn merges_photos_above_the_threshold() {
+        assert_eq!(clusters(0.8, Linkage::Average), [vec![0], vec![1], vec![2]]);
+        assert_eq!(clusters(0.7, Linkage::Average), [vec![0, 1], vec![2]]);
+        assert_eq!(clusters(0.743, Linkage::Average), [vec![0, 1], vec![2]]);
+        assert_eq!(clusters(0.744, Linkage::Average), [vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn links_clusters() {
+        assert_eq!(clusters(0.5, Linkage::Single), [vec![0, 1, 2]]);
+        assert_eq!(clusters(0.5, Linkage::Complete), [vec![0, 1], vec![2]]);
+        assert_eq!(clusters(0.5, Linkage::Average), [vec![0, 1], vec![2]]);
+        assert_eq!(clusters(0.3, Linkage::Average), [vec![0, 1, 2]]);
+        assert_eq!(clusters(0.3, Linkage::Complete), [vec![0, 1], vec![2]]);
+    }
+
+    #[test]
+    fn represents_clusters_by_their_best_celebrities() {
+        let results = vec![
+            cards(&[("Ann", 90.0), ("Bea", 40.0), ("Cid", 30.0)]),
+            cards(&[("Ann", 80.0), ("Bea", 50.0)]),
+            cards(&[("Dee", 70.0)])
+        ];
+        let clusters = Clustering::new(&Client::new()).with_representatives(2).cluster(&results);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].photos, [0, 1]);
+        let names: Vec<&str> = clusters[0].celebrities.iter().map(|celebrity| celebrity.celebrity.name.as_str()).collect();
+        assert_eq!(names, ["Ann", "Bea"]);
+        assert_eq!(clusters[0].celebrities[0].mean, 85.0);
+        assert_eq!(clusters[1].photos, [2]);
+        assert_eq!(clusters[1].celebrities[0].celebrity.name, "Dee");
+    }
+
+    #[test]
+    fn keeps_photos_without_celebrities_apart() {
+        let results = vec![cards(&[]), cards(&[("Ann", 90.0)]), cards(&[])];
+        let clusters = Clustering::new(&Client::new()).with_threshold(0.01).cluster(&results);
+        assert_eq!(clusters.into_iter().map(|cluster| cluster.photos).collect::<Vec<_>>(), [vec![0], vec![1], vec![2]]);
+        assert!(Clustering::new(&Client::new()).cluster(&[]).is_empty());
+    }
+}
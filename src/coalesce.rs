@@ -0,0 +1,200 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Deduplicates concurrent calls sharing a key.
+///
+/// The first caller runs the call, later callers wait
+/// for it and receive a clone of its result.
+#[doc(hidden)]
+#[derive(Debug)]
+pub(crate) struct Coalescer<T> {
+    inflight: Mutex<HashMap<String, Arc<Call<T>>>>
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+struct Call<T> {
+    result: Mutex<Option<T>>,
+    ready: Condvar
+}
+
+#[doc(hidden)]
+struct Leader<'a, T> {
+    coalescer: &'a Coalescer<T>,
+    key: &'a str,
+    call: Arc<Call<T>>,
+    fallback: Option<T>
+}
+
+impl<T: Clone> Coalescer<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// `key` Calls sharing a key run once at a time.
+    ///
+    /// `fallback` Result given to waiting callers if the call panics.
+    ///
+    /// Return the result and whether it was shared from another caller.
+    pub(crate) fn run<F: FnOnce() -> T>(&self, key: &str, fallback: T, call: F) -> (T, bool) {
+        let (shared, leader) = {
+            let mut inflight = lock(&self.inflight);
+            match inflight.get(key) {
+                Some(shared) => (shared.clone(), false),
+                None => {
+                    let shared = Arc::new(Call {
+                        result: Mutex::new(None),
+                        ready: Condvar::new()
+                    });
+                    inflight.insert(key.to_string(), shared.clone());
+                    (shared, true)
+                }
+            }
+        };
+        if !leader {
+            let mut result = lock(&shared.result);
+            loop {
+                if let Some(result) = result.as_ref() {
+                    return (result.clone(), true)
+                }
+                result = match shared.ready.wait(result) {
+                    Ok(result) => result,
+                    Err(poisoned) => poisoned.into_inner()
+                };
+            }
+        }
+        let mut guard = Leader {
+            coalescer: self,
+            key,
+            call: shared,
+            fallback: Some(fallback)
+        };
+        let result = call();
+        guard.fallback = Some(result.clone());
+        (result, false)
+    }
+}
+
+impl<T> Drop for Leader<'_, T> {
+    fn drop(&mut self) {
+        lock(&self.coalescer.inflight).remove(self.key);
+        *lock(&self.call.result) = self.fallback.take();
+        self.call.ready.notify_all();
+    }
+}
+
+#[doc(hidden)]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use super::Coalescer;
+
+    #[test]
+    fn leader_runs_the_call() {
+        let coalescer = Coalescer::new();
+        assert_eq!(coalescer.run("key", 0, || 1), (1, false));
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sequential_calls_run_again() {
+        let coalescer = Coalescer::new();
+        let calls = AtomicUsize::new(0);
+        for _ in 0..3 {
+            assert!(!coalescer.run("key", 0, || calls.fetch_add(1, Ordering::SeqCst) + 1).1);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn waiters_share_the_result() {
+        let coalescer = Arc::new(Coalescer::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8).map(|_| {
+            let (coalescer, calls, barrier) = (coalescer.clone(), calls.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                coalescer.run("key", 0, || {
+                    thread::sleep(Duration::from_millis(200));
+                    calls.fetch_add(1, Ordering::SeqCst) + 42
+                })
+            })
+        }).collect();
+        let results: Vec<(usize, bool)> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|&(result, _)| result == 42));
+        assert_eq!(results.iter().filter(|&&(_, shared)| !shared).count(), 1);
+    }
+
+    #[test]
+    fn keys_run_separately() {
+        let coalescer = Arc::new(Coalescer::new());
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = ["a", "b"].into_iter().map(|key| {
+            let (coalescer, barrier) = (coalescer.clone(), barrier.clone());
+            thread::spawn(move || coalescer.run(key, String::new(), || {
+                barrier.wait();
+                key.to_string()
+            }))
+        }).collect();
+        let results: Vec<(String, bool)> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(results, [("a".to_string(), false), ("b".to_string(), false)]);
+    }
+
+    #[test]
+    fn waiters_get_the_fallback_if_the_leader_panics() {
+        let coalescer = Arc::new(Coalescer::new());
+        let (started, release) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+        let leader = {
+            let (coalescer, started, release) = (coalescer.clone(), started.clone(), release.clone());
+            thread::spawn(move || panic::catch_unwind(AssertUnwindSafe(|| coalescer.run("key", -1, || {
+                started.wait();
+                release.wait();
+                panic!("lookup panicked")
+            }))))
+        };
+        started.wait();
+        let waiter = {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || coalescer.run("key", -2, || 0))
+        };
+        // The map, the leader and the waiter hold the call.
+        while Arc::strong_count(&coalescer.inflight.lock().unwrap()["key"]) < 3 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        release.wait();
+        assert!(leader.join().unwrap().is_err());
+        assert_eq!(waiter.join().unwrap(), (-1, true));
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+        assert_eq!(coalescer.run("key", 0, || 7), (7, false));
+    }
+}
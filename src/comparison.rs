@@ -0,0 +1,242 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{BTreeSet, HashMap};
+use crate::{Batch, Celebrity, Client, Lookup, Source, StarByFaceError};
+use crate::consensus::key;
+
+/// Celebrity found for both subjects of a `Comparison`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedCelebrity {
+    /// Card of the first subject with the highest similarity.
+    pub celebrity: Celebrity,
+    /// Similarity to the first subject in percent.
+    pub similarity_a: f64,
+    /// Similarity to the second subject in percent.
+    pub similarity_b: f64
+}
+
+/// Lookalikes two subjects have in common.
+///
+/// Celebrities are matched by their wiki link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Celebrities found for both subjects, highest lower
+    /// similarity first.
+    pub shared: Vec<SharedCelebrity>,
+    /// Shared celebrities over the celebrities of either
+    /// subject, from 0 to 1.
+    pub overlap: f64,
+    /// Spearman correlation of the similarity rankings, a
+    /// celebrity missing from a ranking being ranked last,
+    /// `None` with fewer than two celebrities or no spread.
+    pub rank_correlation: Option<f64>,
+    /// Sum of the lower similarity of each celebrity over the
+    /// sum of the higher, a missing celebrity having a
+    /// similarity of 0, from 0 to 1.
+    pub score: f64
+}
+
+impl Comparison {
+
+    /// `a` Celebrities of the first subject.
+    ///
+    /// `b` Celebrities of the second subject.
+    pub fn new(a: &[Celebrity], b: &[Celebrity]) -> Self {
+        let similarities_a = similarities(a);
+        let similarities_b = similarities(b);
+        let keys: BTreeSet<&str> = similarities_a.keys().chain(similarities_b.keys()).copied().collect();
+        let mut best: HashMap<&str, &Celebrity> = HashMap::new();
+        for celebrity in a {
+            let best = best.entry(key(celebrity)).or_insert(celebrity);
+            if celebrity.similarity > best.similarity {
+                *best = celebrity;
+            }
+        }
+        let mut shared = vec![];
+        for celebrity in a {
+            if let (Some(&similarity_b), Some(best)) = (similarities_b.get(key(celebrity)), best.remove(key(celebrity))) {
+                shared.push(SharedCelebrity {
+                    celebrity: best.clone(),
+                    similarity_a: best.similarity,
+                    similarity_b
+                });
+            }
+        }
+        let mut lower = 0.0;
+        let mut higher = 0.0;
+        for key in &keys {
+            let similarity_a = similarities_a.get(key).copied().unwrap_or(0.0);
+            let similarity_b = similarities_b.get(key).copied().unwrap_or(0.0);
+            lower += similarity_a.min(similarity_b);
+            higher += similarity_a.max(similarity_b);
+        }
+        shared.sort_by(|x, y| y.similarity_a.min(y.similarity_b).total_cmp(&x.similarity_a.min(x.similarity_b))
+            .then(x.celebrity.name.cmp(&y.celebrity.name)));
+        let keys: Vec<&str> = keys.into_iter().collect();
+        Self {
+            overlap: if keys.is_empty() {
+                0.0
+            } else {
+                shared.len() as f64 / keys.len() as f64
+            },
+            rank_correlation: correlation(&ranks(&keys, &similarities_a), &ranks(&keys, &similarities_b)),
+            score: if higher > 0.0 {
+                lower / higher
+            } else {
+                0.0
+            },
+            shared
+        }
+    }
+
+    /// `a` Image of the first subject.
+    ///
+    /// `b` Image of the second subject.
+    ///
+    /// `client` Client used to send the requests.
+    ///
+    /// A subject without results has no celebrities in common.
+    pub fn lookup(a: &Source, b: &Source, client: &Client) -> Result<Self, StarByFaceError> {
+        let lookups = Batch::new(client).with_concurrency(2).run(&[a.clone(), b.clone()]);
+        Ok(Self::new(&celebrities(&lookups[0])?, &celebrities(&lookups[1])?))
+    }
+}
+
+/// Return the celebrities of a lookup, none if it has no results.
+#[doc(hidden)]
+fn celebrities(lookup: &Lookup) -> Result<Vec<Celebrity>, StarByFaceError> {
+    match lookup.celebrities() {
+        Err(StarByFaceError::Null(_)) => Ok(vec![]),
+        result => result
+    }
+}
+
+/// Return the highest similarity of each celebrity by key.
+#[doc(hidden)]
+fn similarities(celebrities: &[Celebrity]) -> HashMap<&str, f64> {
+    let mut similarities: HashMap<&str, f64> = HashMap::new();
+    for celebrity in celebrities {
+        let similarity = similarities.entry(key(celebrity)).or_insert(celebrity.similarity);
+        *similarity = similarity.max(celebrity.similarity);
+    }
+    similarities
+}
+
+/// Return the rank of each key by descending similarity, ties
+/// and missing celebrities sharing their mean rank.
+#[doc(hidden)]
+fn ranks(keys: &[&str], similarities: &HashMap<&str, f64>) -> Vec<f64> {
+    let values: Vec<f64> = keys.iter().map(|key| similarities.get(key).copied().unwrap_or(f64::NEG_INFINITY)).collect();
+    values.iter()
+        .map(|value| {
+            let above = values.iter().filter(|other| *other > value).count();
+            let equal = values.iter().filter(|other| *other == value).count();
+            above as f64 + (equal as f64 + 1.0) / 2.0
+        })
+        .collect()
+}
+
+/// Return the Pearson correlation of two series, `None` if
+/// either has no spread.
+#[doc(hidden)]
+fn correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+    if x.len() < 2 {
+        return None
+    }
+    let count = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / count;
+    let mean_y = y.iter().sum::<f64>() / count;
+    let covariance: f64 = x.iter().zip(y).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance_x: f64 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let variance_y: f64 = y.iter().map(|y| (y - mean_y).powi(2)).sum();
+    if variance_x > 0.0 && variance_y > 0.0 {
+        Some(covariance / (variance_x * variance_y).sqrt())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::celebrity::test_support::cards;
+    use super::Comparison;
+
+    fn assert_finite(comparison: &Comparison) {
+        assert!(comparison.overlap.is_finite() && comparison.score.is_finite(), "{:?}", comparison);
+        assert!(comparison.rank_correlation.is_none_or(f64::is_finite), "{:?}", comparison);
+    }
+
+    #[test]
+    fn correlates_identical_rankings() {
+        let a = cards(&[("Ann", 90.0), ("Bea", 70.0), ("Cid", 50.0)]);
+        let comparison = Comparison::new(&a, &a);
+        assert_finite(&comparison);
+        assert_eq!(comparison.rank_correlation, Some(1.0));
+        assert_eq!((comparison.overlap, comparison.score), (1.0, 1.0));
+        assert_eq!(comparison.shared.iter().map(|shared| shared.celebrity.name.as_str()).collect::<Vec<_>>(), ["Ann", "Bea", "Cid"]);
+    }
+
+    #[test]
+    fn correlates_reversed_rankings() {
+        let a = cards(&[("Ann", 90.0), ("Bea", 70.0), ("Cid", 50.0)]);
+        let b = cards(&[("Cid", 90.0), ("Bea", 70.0), ("Ann", 50.0)]);
+        let comparison = Comparison::new(&a, &b);
+        assert_finite(&comparison);
+        assert_eq!(comparison.rank_correlation, Some(-1.0));
+        assert_eq!(comparison.overlap, 1.0);
+        assert!((comparison.score - 170.0 / 250.0).abs() < 1e-9, "{}", comparison.score);
+    }
+
+    #[test]
+    fn ranks_missing_celebrities_last_in_disjoint_rankings() {
+        let a = cards(&[("Ann", 90.0), ("Bea", 70.0)]);
+        let b = cards(&[("Cid", 80.0), ("Dee", 60.0)]);
+        let comparison = Comparison::new(&a, &b);
+        assert_finite(&comparison);
+        assert!(comparison.shared.is_empty());
+        assert_eq!((comparison.overlap, comparison.score), (0.0, 0.0));
+        let correlation = comparison.rank_correlation.unwrap();
+        assert!((correlation + 4.0 / 4.5).abs() < 1e-9, "{}", correlation);
+    }
+
+    #[test]
+    fn has_no_correlation_without_spread() {
+        let single = cards(&[("Ann", 90.0)]);
+        for (a, b) in [(&single, &single), (&single, &vec![]), (&vec![], &vec![])] {
+            let comparison = Comparison::new(a, b);
+            assert_finite(&comparison);
+            assert_eq!(comparison.rank_correlation, None);
+        }
+        let empty = Comparison::new(&[], &[]);
+        assert_eq!((empty.overlap, empty.score), (0.0, 0.0));
+        let tied = cards(&[("Ann", 50.0), ("Bea", 50.0)]);
+        let comparison = Comparison::new(&tied, &tied);
+        assert_finite(&comparison);
+        assert_eq!(comparison.rank_correlation, None);
+    }
+
+    #[test]
+    fn keeps_the_highest_similarity_of_duplicates() {
+        let a = cards(&[("Ann", 40.0), ("Ann", 80.0), ("Bea", 60.0)]);
+        let b = cards(&[("Ann", 80.0), ("Bea", 60.0)]);
+        let comparison = Comparison::new(&a, &b);
+        assert_eq!(comparison.rank_correlation, Some(1.0));
+        assert_eq!(comparison.shared.len(), 2);
+        assert_eq!((comparison.shared[0].similarity_a, comparison.shared[0].similarity_b), (80.0, 80.0));
+        assert_eq!((comparison.shared[0].celebrity.rank, comparison.shared[0].celebrity.similarity), (2, 80.0));
+        assert_eq!(comparison.score, 1.0);
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{BTreeMap, HashMap};
+use crate::{Batch, Celebrity, Client, Lookup, Source, StarByFaceError};
+
+/// Photo a celebrity was found in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Provenance {
+    /// Index of the photo in the sources.
+    pub photo: usize,
+    /// Position within its gender section of the photo, starting at 1.
+    pub rank: usize,
+    /// Similarity in percent.
+    pub similarity: f64
+}
+
+/// Celebrity found across the photos of one subject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsensusCelebrity {
+    /// Card with the highest similarity, ranked in the consensus.
+    pub celebrity: Celebrity,
+    /// Number of photos the celebrity was found in.
+    pub frequency: usize,
+    /// Mean similarity in percent, a photo the celebrity
+    /// wasn't found in counts as 0.
+    pub mean: f64,
+    /// Highest similarity in percent.
+    pub max: f64,
+    /// Photos the celebrity was found in.
+    pub photos: Vec<Provenance>
+}
+
+/// Result of a `Consensus`.
+#[derive(Debug, Clone)]
+pub struct ConsensusResult {
+    /// Celebrities of every photo, best first.
+    pub celebrities: Vec<ConsensusCelebrity>,
+    /// Lookup of each photo, in the order of the sources.
+    pub lookups: Vec<Lookup>
+}
+
+/// Looks up several photos of one subject and aggregates
+/// the celebrities they have in common.
+///
+/// Celebrities are deduplicated by their wiki link.
+#[derive(Debug, Clone)]
+pub struct Consensus {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl Consensus {
+
+    /// `client` Client used to send the requests.
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            concurrency: 4
+        }
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `sources` Photos of the subject.
+    ///
+    /// Return the aggregated celebrities, or the error of the
+    /// first photo if none succeeded. Photos that failed count
+    /// for nothing.
+    pub fn run(&self, sources: &[Source]) -> Result<ConsensusResult, StarByFaceError> {
+        let lookups = Batch::new(&self.client).with_concurrency(self.concurrency).run(sources);
+        let results: Vec<(usize, Vec<Celebrity>)> = lookups.iter()
+            .enumerate()
+            .filter_map(|(index, lookup)| lookup.celebrities().ok().map(|celebrities| (index, celebrities)))
+            .collect();
+        if results.is_empty() {
+            return match lookups.first().map(|lookup| lookup.celebrities()) {
+                Some(Err(err)) => Err(err),
+                _ => Err(StarByFaceError::Null(String::from("No photo to look up")))
+            }
+        }
+        let celebrities = aggregate(&results);
+        tracing::info!(photos = lookups.len(), succeeded = results.len(), celebrities = celebrities.len(), "aggregated consensus");
+        Ok(ConsensusResult { celebrities, lookups })
+    }
+}
+
+/// Return the celebrities of the results of several photos,
+/// best first, keeping the best card of each photo.
+#[doc(hidden)]
+pub(crate) fn aggregate(results: &[(usize, Vec<Celebrity>)]) -> Vec<ConsensusCelebrity> {
+    let total = results.len() as f64;
+    let mut celebrities: Vec<ConsensusCelebrity> = group(results).into_iter()
+        .map(|cards| {
+            let cards = best_per_result(&cards);
+            let sum: f64 = cards.iter().map(|(_, card)| card.similarity).sum();
+            ConsensusCelebrity {
+                celebrity: best(&cards),
+                frequency: cards.len(),
+                mean: sum / total,
+                max: cards.iter().map(|(_, card)| card.similarity).fold(f64::MIN, f64::max),
+                photos: cards.iter().map(|&(photo, card)| Provenance {
+                    photo,
+                    rank: card.rank,
+                    similarity: card.similarity
+                }).collect()
+            }
+        })
+        .collect();
+    celebrities.sort_by(|a, b| b.mean.total_cmp(&a.mean)
+        .then(b.frequency.cmp(&a.frequency))
+        .then(a.celebrity.name.cmp(&b.celebrity.name)));
+    for (index, celebrity) in celebrities.iter_mut().enumerate() {
+        celebrity.celebrity.rank = index + 1;
+    }
+    celebrities
+}
+
+/// Return the cards of the results grouped by wiki link, or
+/// by name without one, in the order they were first found.
+#[doc(hidden)]
+pub(crate) fn group(results: &[(usize, Vec<Celebrity>)]) -> Vec<Vec<(usize, &Celebrity)>> {
+    let mut order: Vec<&str> = vec![];
+    let mut found: HashMap<&str, Vec<(usize, &Celebrity)>> = HashMap::new();
+    for (index, celebrities) in results {
+        for celebrity in celebrities {
+            let cards = found.entry(key(celebrity)).or_default();
+            if cards.is_empty() {
+                order.push(key(celebrity));
+            }
+            cards.push((*index, celebrity));
+        }
+    }
+    order.into_iter().filter_map(|key| found.remove(key)).collect()
+}
+
+/// Return the wiki link of the celebrity, or its name without one.
+#[doc(hidden)]
+pub(crate) fn key(celebrity: &Celebrity) -> &str {
+    if celebrity.wiki.is_empty() {
+        celebrity.name.as_str()
+    } else {
+        celebrity.wiki.as_str()
+    }
+}
+
+/// Return the card with the highest similarity of each result
+/// of a group, in result order.
+#[doc(hidden)]
+pub(crate) fn best_per_result<'a>(cards: &[(usize, &'a Celebrity)]) -> Vec<(usize, &'a Celebrity)> {
+    let mut found: BTreeMap<usize, &Celebrity> = BTreeMap::new();
+    for &(index, card) in cards {
+        let best = found.entry(index).or_insert(card);
+        if card.similarity > best.similarity {
+            *best = card;
+        }
+    }
+    found.into_iter().collect()
+}
+
+/// Return the card with the highest similarity of a group.
+#[doc(hidden)]
+pub(crate) fn best(cards: &[(usize, &Celebrity)]) -> Celebrity {
+    let mut best = cards[0].1;
+    for &(_, card) in cards {
+        if card.similarity > best.similarity {
+            best = card;
+        }
+    }
+    best.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::celebrity::test_support::card;
+    use super::{aggregate, Provenance};
+
+    #[test]
+    fn averages_the_best_card_of_each_photo() {
+        let results = vec![
+            (0, vec![card("Ann", 1, 90.0), card("Bea", 2, 60.0), card("Ann", 3, 40.0)]),
+            (2, vec![card("Bea", 1, 80.0), card("Ann", 2, 30.0)])
+        ];
+        let celebrities = aggregate(&results);
+        assert_eq!(celebrities.len(), 2);
+        let ann = &celebrities[1];
+        assert_eq!((ann.celebrity.name.as_str(), ann.celebrity.rank, ann.celebrity.similarity), ("Ann", 2, 90.0));
+        assert_eq!(ann.frequency, 2);
+        assert_eq!(ann.mean, 60.0);
+        assert_eq!(ann.max, 90.0);
+        assert_eq!(ann.photos, [Provenance { photo: 0, rank: 1, similarity: 90.0 }, Provenance { photo: 2, rank: 2, similarity: 30.0 }]);
+        let bea = &celebrities[0];
+        assert_eq!((bea.celebrity.rank, bea.frequency, bea.mean, bea.max), (1, 2, 70.0, 80.0));
+    }
+
+    #[test]
+    fn counts_missing_photos_as_zero() {
+        let results = vec![
+            (0, vec![card("Ann", 1, 50.0), card("Ann", 2, 50.0)]),
+            (1, vec![]),
+            (2, vec![card("Bea", 1, 20.0)]),
+            (3, vec![])
+        ];
+        let celebrities = aggregate(&results);
+        assert_eq!(celebrities.iter().map(|celebrity| (celebrity.celebrity.name.as_str(), celebrity.mean)).collect::<Vec<_>>(),
+                   [("Ann", 12.5), ("Bea", 5.0)]);
+        assert_eq!(celebrities[0].frequency, 1);
+    }
+
+    #[test]
+    fn ranks_ties_by_frequency_then_name() {
+        let results = vec![
+            (0, vec![card("Cid", 1, 60.0), card("Bea", 2, 30.0)]),
+            (1, vec![card("Bea", 1, 30.0), card("Ann", 2, 30.0), card("Ann", 3, 30.0)])
+        ];
+        let names: Vec<String> = aggregate(&results).into_iter().map(|celebrity| celebrity.celebrity.name).collect();
+        assert_eq!(names, ["Bea", "Cid", "Ann"]);
+        assert!(aggregate(&[]).is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use image::DynamicImage;
+use crate::{Batch, Celebrity, Client, Lookup, Source, StarByFaceError};
+use crate::consensus::{best, group};
+use crate::preprocess::{decode, encode, is_oriented};
+
+/// Variant of an image submitted by an `Ensemble`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Augmentation {
+    /// Image unchanged, or with its EXIF orientation applied.
+    Original,
+    /// Image flipped horizontally.
+    Mirror,
+    /// Crop keeping `scale` of the width and height, positioned
+    /// by `x` and `y` from 0 for left and top to 1 for right
+    /// and bottom.
+    Crop {
+        x: f64,
+        y: f64,
+        scale: f64
+    },
+    /// Brightness shifted by this amount, from -255 to 255.
+    Brightness(i32)
+}
+
+impl Augmentation {
+
+    /// Return the variant of the image.
+    #[doc(hidden)]
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        match *self {
+            Augmentation::Original => image.clone(),
+            Augmentation::Mirror => image.fliph(),
+            Augmentation::Crop { x, y, scale } => {
+                let scale = scale.clamp(0.0, 1.0);
+                let width = ((image.width() as f64 * scale) as u32).max(1);
+                let height = ((image.height() as f64 * scale) as u32).max(1);
+                let left = ((image.width() - width) as f64 * x.clamp(0.0, 1.0)) as u32;
+                let top = ((image.height() - height) as f64 * y.clamp(0.0, 1.0)) as u32;
+                image.crop_imm(left, top, width, height)
+            },
+            Augmentation::Brightness(value) => image.brighten(value)
+        }
+    }
+}
+
+impl Display for Augmentation {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            Augmentation::Original => write!(f, "original"),
+            Augmentation::Mirror => write!(f, "mirror"),
+            Augmentation::Crop { x, y, scale } => write!(f, "crop {:.0}% at {:.1},{:.1}",scale * 100.0,x,y),
+            Augmentation::Brightness(value) => write!(f, "brightness {:+}",value)
+        }
+    }
+}
+
+/// Similarity used to rank the celebrities of an `Ensemble`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aggregation {
+    /// Mean similarity, the default.
+    Mean,
+    /// Highest similarity.
+    Max,
+    /// Number of variants the celebrity was found in.
+    Votes
+}
+
+/// Celebrity merged across the variants of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnsembleCelebrity {
+    /// Card with the highest similarity, ranked in the merged ranking.
+    pub celebrity: Celebrity,
+    /// Mean similarity in percent, a variant the celebrity
+    /// wasn't found in counts as 0.
+    pub mean: f64,
+    /// Highest similarity in percent.
+    pub max: f64,
+    /// Number of variants the celebrity was found in.
+    pub votes: usize,
+    /// Share of the variants the celebrity was found in, lowered
+    /// by the spread of its similarity, from 0 to 1.
+    pub stability: f64
+}
+
+/// Result of an `Ensemble`.
+#[derive(Debug, Clone)]
+pub struct EnsembleResult {
+    /// Celebrities of every variant, best first.
+    pub celebrities: Vec<EnsembleCelebrity>,
+    /// Lookup of each variant.
+    pub lookups: Vec<(Augmentation, Lookup)>
+}
+
+/// Looks up several variants of an image and merges their
+/// results into a more stable ranking.
+///
+/// Celebrities are matched across variants by their wiki link.
+#[derive(Debug, Clone)]
+pub struct Ensemble {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    augmentations: Vec<Augmentation>,
+    #[doc(hidden)]
+    aggregation: Aggregation,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl Ensemble {
+
+    /// `client` Client used to send the requests.
+    ///
+    /// By default the original, its mirror, three crops of 90%
+    /// and two brightness shifts of 20 are looked up.
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            augmentations: vec![
+                Augmentation::Original,
+                Augmentation::Mirror,
+                Augmentation::Crop { x: 0.5, y: 0.5, scale: 0.9 },
+                Augmentation::Crop { x: 0.0, y: 0.0, scale: 0.9 },
+                Augmentation::Crop { x: 1.0, y: 1.0, scale: 0.9 },
+                Augmentation::Brightness(20),
+                Augmentation::Brightness(-20)
+            ],
+            aggregation: Aggregation::Mean,
+            concurrency: 4
+        }
+    }
+
+    /// `augmentations` Variants looked up.
+    pub fn with_augmentations(mut self, augmentations: Vec<Augmentation>) -> Self {
+        self.augmentations = augmentations;
+        self
+    }
+
+    /// `aggregation` Similarity used to rank the celebrities,
+    /// `Aggregation::Mean` by default.
+    pub fn with_aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+        self
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `source` Image file or bytes.
+    ///
+    /// Return the merged ranking, or the error of the first
+    /// variant if none succeeded.
+    pub fn run(&self, source: &Source) -> Result<EnsembleResult, StarByFaceError> {
+        let sources = self.variants(source)?;
+        let lookups = Batch::new(&self.client).with_concurrency(self.concurrency).run(&sources);
+        let results: Vec<(usize, Vec<Celebrity>)> = lookups.iter()
+            .enumerate()
+            .filter_map(|(index, lookup)| lookup.celebrities().ok().map(|celebrities| (index, celebrities)))
+            .collect();
+        if results.is_empty() {
+            return match lookups.first().map(|lookup| lookup.celebrities()) {
+                Some(Err(err)) => Err(err),
+                _ => Err(StarByFaceError::Null(String::from("No variant to look up")))
+            }
+        }
+        let mut celebrities = merge(&results);
+        celebrities.sort_by(|a, b| {
+            let (a_score, b_score) = match self.aggregation {
+                Aggregation::Mean => (a.mean, b.mean),
+                Aggregation::Max => (a.max, b.max),
+                Aggregation::Votes => (a.votes as f64, b.votes as f64)
+            };
+            b_score.total_cmp(&a_score)
+                .then(b.mean.total_cmp(&a.mean))
+                .then(a.celebrity.name.cmp(&b.celebrity.name))
+        });
+        for (index, celebrity) in celebrities.iter_mut().enumerate() {
+            celebrity.celebrity.rank = index + 1;
+        }
+        tracing::info!(variants = lookups.len(), succeeded = results.len(), celebrities = celebrities.len(), "merged ensemble");
+        Ok(EnsembleResult {
+            celebrities,
+            lookups: self.augmentations.iter().copied().zip(lookups).collect()
+        })
+    }
+
+    /// Return the variants of the image to look up.
+    #[doc(hidden)]
+    fn variants(&self, source: &Source) -> Result<Vec<Source>, StarByFaceError> {
+        if let Source::Url(_) = *source {
+            return Err(StarByFaceError::Error(String::from("Variants can only be made of files and bytes")))
+        }
+        let (data, file_name) = source.read()?;
+        let image = decode(&data)?;
+        let stem = match file_name.rsplit_once('.') {
+            Some((stem, _)) => stem.to_string(),
+            None => file_name.clone()
+        };
+        let oriented = is_oriented(&data);
+        let mut variants = vec![];
+        for (index, augmentation) in self.augmentations.iter().enumerate() {
+            variants.push(match augmentation {
+                Augmentation::Original if !oriented => Source::Bytes { data: data.clone(), file_name: file_name.clone() },
+                augmentation => Source::Bytes {
+                    data: encode(&augmentation.apply(&image))?,
+                    file_name: format!("{}-variant-{}.jpg",stem,index + 1)
+                }
+            });
+        }
+        Ok(variants)
+    }
+}
+
+/// Return the celebrities of the results merged by wiki link,
+/// keeping the best card of each variant.
+#[doc(hidden)]
+fn merge(results: &[(usize, Vec<Celebrity>)]) -> Vec<EnsembleCelebrity> {
+    let total = results.len() as f64;
+    group(results).into_iter()
+        .map(|cards| {
+            let cards = best_per_variant(&cards);
+            let votes = cards.len();
+            let similarities: Vec<f64> = cards.iter().map(|(_, card)| card.similarity).collect();
+            let sum: f64 = similarities.iter().sum();
+            let present_mean = sum / votes as f64;
+            let deviation = (similarities.iter().map(|similarity| (similarity - present_mean).powi(2)).sum::<f64>() / votes as f64).sqrt();
+            let consistency = if present_mean > 0.0 {
+                (1.0 - deviation / present_mean).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            EnsembleCelebrity {
+                celebrity: best(&cards),
+                mean: sum / total,
+                max: similarities.iter().copied().fold(f64::MIN, f64::max),
+                votes,
+                stability: votes as f64 / total * consistency
+            }
+        })
+        .collect()
+}
+
+/// Return the card with the highest similarity of each variant
+/// of a group, in variant order.
+#[doc(hidden)]
+fn best_per_variant<'a>(cards: &[(usize, &'a Celebrity)]) -> Vec<(usize, &'a Celebrity)> {
+    let mut found: BTreeMap<usize, &Celebrity> = BTreeMap::new();
+    for &(variant, card) in cards {
+        let best = found.entry(variant).or_insert(card);
+        if card.similarity > best.similarity {
+            *best = card;
+        }
+    }
+    found.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use image::{DynamicImage, GenericImageView, ImageFormat, RgbImage};
+    use crate::{Client, Source};
+    use crate::celebrity::test_support::card;
+    use super::{merge, Augmentation, Ensemble};
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn merges_the_best_card_of_each_variant() {
+        let results = vec![
+            (0, vec![card("Ann", 1, 80.0), card("Ann", 1, 60.0), card("Bea", 1, 50.0)]),
+            (1, vec![card("Ann", 1, 70.0)]),
+            (2, vec![])
+        ];
+        let merged = merge(&results);
+        assert_eq!(merged.len(), 2);
+        let ann = &merged[0];
+        assert_eq!((ann.celebrity.name.as_str(), ann.celebrity.similarity), ("Ann", 80.0));
+        assert_eq!(ann.votes, 2);
+        assert_close(ann.mean, 50.0);
+        assert_close(ann.max, 80.0);
+        assert_close(ann.stability, 2.0 / 3.0 * (1.0 - 5.0 / 75.0));
+        let bea = &merged[1];
+        assert_eq!(bea.votes, 1);
+        assert_close(bea.mean, 50.0 / 3.0);
+        assert_close(bea.stability, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn keeps_stability_within_bounds() {
+        let results = vec![(0, vec![card("Ann", 1, 50.0), card("Ann", 1, 50.0), card("Ann", 1, 50.0)])];
+        let merged = merge(&results);
+        assert_eq!(merged[0].votes, 1);
+        assert_close(merged[0].mean, 50.0);
+        assert_close(merged[0].stability, 1.0);
+        let results = vec![(0, vec![card("Ann", 1, 0.0)]), (1, vec![card("Ann", 1, 0.0)])];
+        assert_close(merge(&results)[0].stability, 1.0);
+        let results = vec![(0, vec![card("Ann", 1, 100.0)]), (1, vec![card("Ann", 1, 1.0)]), (2, vec![card("Ann", 1, 1.0)])];
+        let stability = merge(&results)[0].stability;
+        assert!((0.0..=1.0).contains(&stability), "{}", stability);
+    }
+
+    #[test]
+    fn orients_the_original() {
+        let mut jpeg = vec![];
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20)).write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+        // APP1 segment with an EXIF orientation of 6, rotating the image by 90 degrees.
+        let mut exif = b"\xFF\xE1\0\x22Exif\0\0MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0".to_vec();
+        exif.splice(0..0, jpeg[..2].iter().copied());
+        exif.extend_from_slice(&jpeg[2..]);
+        let ensemble = Ensemble::new(&Client::new()).with_augmentations(vec![Augmentation::Original]);
+        let source = Source::Bytes { data: exif, file_name: "face.jpg".to_string() };
+        let variants = ensemble.variants(&source).unwrap();
+        match &variants[0] {
+            Source::Bytes { data, file_name } => {
+                assert_eq!(file_name, "face-variant-1.jpg");
+                assert_eq!(image::load_from_memory(data).unwrap().dimensions(), (20, 40));
+            },
+            source => panic!("{:?}", source)
+        }
+        let source = Source::Bytes { data: jpeg.clone(), file_name: "face.jpg".to_string() };
+        match &ensemble.variants(&source).unwrap()[0] {
+            Source::Bytes { data, file_name } => assert_eq!((data, file_name.as_str()), (&jpeg, "face.jpg")),
+            source => panic!("{:?}", source)
+        }
+    }
+}
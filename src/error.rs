@@ -15,11 +15,66 @@
  */
 use std::error::Error;
 use std::fmt::{Display, Result, Formatter};
+use crate::{Diagnostics, QualityIssue};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum StarByFaceError {
     Error(String),
     Null(String),
+    /// Upstream answered with a non-2xx status.
+    Status {
+        status: u16,
+        body: String
+    },
+    /// Upstream answered with neither HTML nor JSON.
+    ContentType {
+        status: u16,
+        content_type: String,
+        body: String
+    },
+    /// Upstream answered with a bot challenge page.
+    Challenge {
+        status: u16,
+        body: String
+    },
+    /// Upstream answered with an empty body.
+    EmptyBody {
+        status: u16
+    },
+    /// Response looks like a results page but no card was parsed.
+    MarkupChanged(Diagnostics),
+    /// No face was detected in the image before uploading it.
+    NoFace,
+    /// Several faces were detected in the image before
+    /// uploading it.
+    MultipleFaces(usize),
+    /// Quality of the image was below the thresholds before
+    /// uploading it.
+    LowQuality(Vec<QualityIssue>),
+    /// Image is malformed, or its format isn't supported, before
+    /// uploading it.
+    InvalidImage(String)
+}
+
+impl StarByFaceError {
+
+    /// Return the name of the variant in snake case.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            StarByFaceError::Error(_) => "error",
+            StarByFaceError::Null(_) => "null",
+            StarByFaceError::Status { .. } => "status",
+            StarByFaceError::ContentType { .. } => "content_type",
+            StarByFaceError::Challenge { .. } => "challenge",
+            StarByFaceError::EmptyBody { .. } => "empty_body",
+            StarByFaceError::MarkupChanged(_) => "markup_changed",
+            StarByFaceError::NoFace => "no_face",
+            StarByFaceError::MultipleFaces(_) => "multiple_faces",
+            StarByFaceError::LowQuality(_) => "low_quality",
+            StarByFaceError::InvalidImage(_) => "invalid_image"
+        }
+    }
 }
 
 impl Error for StarByFaceError {}
@@ -28,7 +83,19 @@ impl Display for StarByFaceError {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match *self {
             StarByFaceError::Error(ref message) => write!(f, "{}",message),
-            StarByFaceError::Null(ref message) => write!(f, "{}",message)
+            StarByFaceError::Null(ref message) => write!(f, "{}",message),
+            StarByFaceError::Status { status, ref body } => write!(f, "HTTP status {}: {}",status,body),
+            StarByFaceError::ContentType { status, ref content_type, ref body } => write!(f, "Unexpected content type \"{}\" (HTTP status {}): {}",content_type,status,body),
+            StarByFaceError::Challenge { status, ref body } => write!(f, "Bot challenge (HTTP status {}): {}",status,body),
+            StarByFaceError::EmptyBody { status } => write!(f, "Empty body (HTTP status {})",status),
+            StarByFaceError::MarkupChanged(ref diagnostics) => write!(f, "Markup changed ({})",diagnostics),
+            StarByFaceError::NoFace => write!(f, "No face detected in the image"),
+            StarByFaceError::MultipleFaces(faces) => write!(f, "{} faces detected in the image, expected one",faces),
+            StarByFaceError::LowQuality(ref issues) => write!(f, "Image quality too low: {}",issues.iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")),
+            StarByFaceError::InvalidImage(ref message) => write!(f, "Invalid image: {}",message)
         }
     }
 }
\ No newline at end of file
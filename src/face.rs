@@ -0,0 +1,213 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::fmt::{Debug, Formatter};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use rustface::{ImageData, Model};
+use crate::StarByFaceError;
+use crate::preprocess::decode;
+
+#[doc(hidden)]
+const DETECTION_DIMENSION: u32 = 1024;
+
+/// Region of an image, in pixels of the image with its EXIF
+/// orientation applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+/// Face found by a `FaceDetector`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Face {
+    pub bounding_box: BoundingBox,
+    /// Detection score, higher is more confident.
+    pub score: f64
+}
+
+/// CPU-only face detector run on files and bytes before they
+/// are uploaded, built on the SeetaFace frontal model of
+/// [rustface](https://crates.io/crates/rustface).
+///
+/// Clones share the model.
+#[derive(Clone)]
+pub struct FaceDetector {
+    #[doc(hidden)]
+    model: Arc<Model>,
+    #[doc(hidden)]
+    min_face_size: u32,
+    #[doc(hidden)]
+    score_threshold: f64,
+    #[doc(hidden)]
+    enforcement: bool
+}
+
+impl Debug for FaceDetector {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("FaceDetector")
+            .field("min_face_size", &self.min_face_size)
+            .field("score_threshold", &self.score_threshold)
+            .field("enforcement", &self.enforcement)
+            .finish()
+    }
+}
+
+impl FaceDetector {
+
+    /// `model` SeetaFace model, such as `seeta_fd_frontal_v1.0.bin`.
+    pub fn new<R: Read>(model: R) -> Result<Self, StarByFaceError> {
+        match rustface::read_model(model) {
+            Ok(model) => Ok(Self {
+                model: Arc::new(model),
+                min_face_size: 20,
+                score_threshold: 2.0,
+                enforcement: true
+            }),
+            Err(err) => Err(StarByFaceError::Error(format!("Failed to read face model: {}",err)))
+        }
+    }
+
+    /// `path` SeetaFace model file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, StarByFaceError> {
+        match std::fs::File::open(path) {
+            Ok(file) => Self::new(std::io::BufReader::new(file)),
+            Err(err) => Err(StarByFaceError::Error(format!("Failed to read face model: {}",err)))
+        }
+    }
+
+    /// `min_face_size` Smallest face detected, in pixels of an
+    /// image downscaled to 1024 pixels, 20 by default.
+    pub fn with_min_face_size(mut self, min_face_size: u32) -> Self {
+        self.min_face_size = min_face_size.max(20);
+        self
+    }
+
+    /// `score_threshold` Lowest score of a face, 2.0 by default.
+    pub fn with_score_threshold(mut self, score_threshold: f64) -> Self {
+        self.score_threshold = score_threshold;
+        self
+    }
+
+    /// `enforcement` Fail lookups of images without exactly one
+    /// face with `NoFace` or `MultipleFaces`, true by default.
+    /// Otherwise the faces are only counted.
+    pub fn with_enforcement(mut self, enforcement: bool) -> Self {
+        self.enforcement = enforcement;
+        self
+    }
+
+    /// `data` Image content.
+    ///
+    /// Return faces of the image, highest score first.
+    pub fn detect(&self, data: &[u8]) -> Result<Vec<Face>, StarByFaceError> {
+        Ok(self.detect_image(&decode(data)?))
+    }
+
+    /// Return faces of a decoded image, highest score first.
+    #[doc(hidden)]
+    pub(crate) fn detect_image(&self, image: &DynamicImage) -> Vec<Face> {
+        let scale = (image.width().max(image.height()) as f64 / DETECTION_DIMENSION as f64).max(1.0);
+        let gray = if scale > 1.0 {
+            image.resize(DETECTION_DIMENSION, DETECTION_DIMENSION, FilterType::Triangle).to_luma8()
+        } else {
+            image.to_luma8()
+        };
+        let mut detector = rustface::create_detector_with_model((*self.model).clone());
+        detector.set_min_face_size(self.min_face_size);
+        detector.set_score_thresh(self.score_threshold);
+        detector.set_pyramid_scale_factor(0.8);
+        detector.set_slide_window_step(4, 4);
+        let (width, height) = gray.dimensions();
+        let mut faces: Vec<Face> = detector.detect(&ImageData::new(gray.as_raw(), width, height)).iter()
+            .map(|face| {
+                let bbox = face.bbox();
+                let x = (bbox.x().max(0) as f64 * scale) as u32;
+                let y = (bbox.y().max(0) as f64 * scale) as u32;
+                Face {
+                    bounding_box: BoundingBox {
+                        x: x.min(image.width()),
+                        y: y.min(image.height()),
+                        width: ((bbox.width() as f64 * scale) as u32).min(image.width() - x.min(image.width())),
+                        height: ((bbox.height() as f64 * scale) as u32).min(image.height() - y.min(image.height()))
+                    },
+                    score: face.score()
+                }
+            })
+            .collect();
+        faces.sort_by(|a, b| b.score.total_cmp(&a.score));
+        faces
+    }
+
+    /// Return the number of faces, or an error if enforced and
+    /// the image doesn't have exactly one.
+    #[doc(hidden)]
+    pub(crate) fn check(&self, data: &[u8]) -> Result<usize, StarByFaceError> {
+        face_count(self.detect(data)?.len(), self.enforcement)
+    }
+}
+
+/// `faces` Number of faces detected.
+///
+/// `enforcement` Fail instead of warning if there isn't exactly one.
+///
+/// Return the number of faces, or `NoFace` or `MultipleFaces` if enforced.
+#[doc(hidden)]
+fn face_count(faces: usize, enforcement: bool) -> Result<usize, StarByFaceError> {
+    match faces {
+        1 => Ok(faces),
+        0 if enforcement => Err(StarByFaceError::NoFace),
+        _ if enforcement => Err(StarByFaceError::MultipleFaces(faces)),
+        _ => {
+            tracing::warn!(faces, "expected one face");
+            Ok(faces)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StarByFaceError;
+    use super::face_count;
+
+    #[test]
+    fn one_face_passes() {
+        assert!(matches!(face_count(1, true), Ok(1)));
+        assert!(matches!(face_count(1, false), Ok(1)));
+    }
+
+    #[test]
+    fn no_face_fails_when_enforced() {
+        assert!(matches!(face_count(0, true), Err(StarByFaceError::NoFace)));
+    }
+
+    #[test]
+    fn multiple_faces_fail_when_enforced() {
+        assert!(matches!(face_count(2, true), Err(StarByFaceError::MultipleFaces(2))));
+        assert!(matches!(face_count(5, true), Err(StarByFaceError::MultipleFaces(5))));
+    }
+
+    #[test]
+    fn any_count_passes_when_not_enforced() {
+        assert!(matches!(face_count(0, false), Ok(0)));
+        assert!(matches!(face_count(3, false), Ok(3)));
+    }
+}
@@ -31,21 +31,103 @@
 mod star_by_face_uri;
 mod star_by_face_file;
 mod error;
+mod client;
+mod rate_limit;
+mod page;
+mod celebrity;
+mod source;
+mod lookup;
+mod coalesce;
+mod batch;
+mod telemetry;
+mod preprocess;
+mod metadata;
+mod quality;
+mod consensus;
+mod comparison;
+mod cluster;
+#[cfg(feature = "image")]
+mod ensemble;
+#[cfg(feature = "image")]
+mod animation;
+#[cfg(feature = "face")]
+mod face;
+#[cfg(feature = "face")]
+mod multi_face;
+#[cfg(feature = "face")]
+mod align;
 pub use star_by_face_uri::StarByFace;
 pub use star_by_face_file::StarByFaceFile;
 pub use error::StarByFaceError;
+pub use client::Client;
+pub use rate_limit::RateLimiter;
+pub use page::Diagnostics;
+pub use celebrity::Celebrity;
+pub use source::Source;
+pub use lookup::Lookup;
+pub use batch::Batch;
+pub use telemetry::describe_metrics;
+pub use preprocess::Preprocessing;
+pub use metadata::strip_metadata;
+pub use quality::{Quality, QualityIssue};
+pub use consensus::{Consensus, ConsensusCelebrity, ConsensusResult, Provenance};
+pub use comparison::{Comparison, SharedCelebrity};
+pub use cluster::{Cluster, Clustering, ClusteringResult, Linkage};
+#[cfg(feature = "face")]
+pub use face::{BoundingBox, Face, FaceDetector};
+#[cfg(feature = "face")]
+pub use multi_face::MultiFace;
+#[cfg(feature = "face")]
+pub use align::{Aligner, Alignment};
+#[cfg(feature = "image")]
+pub use preprocess::Preprocessor;
+#[cfg(feature = "image")]
+pub use quality::QualityAnalyzer;
+#[cfg(feature = "image")]
+pub use ensemble::{Aggregation, Augmentation, Ensemble, EnsembleCelebrity, EnsembleResult};
+#[cfg(feature = "image")]
+pub use animation::{Animation, AnimationResult, FrameLookup, FrameSelection};
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Gender {
     FEMALE,
-    MALE
+    MALE,
+    /// Section of the result page other than male or female.
+    Other(String)
 }
 
+impl Gender {
+    #[doc(hidden)]
+    pub(crate) fn from_section(section: &str) -> Self {
+        match section {
+            "female" => Gender::FEMALE,
+            "male" => Gender::MALE,
+            _ => Gender::Other(section.to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for Gender {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Gender::FEMALE => write!(f, "female"),
+            Gender::MALE => write!(f, "male"),
+            Gender::Other(ref section) => write!(f, "{}",section)
+        }
+    }
+}
+
+#[non_exhaustive]
 pub enum Key {
     SIMILARITY,
     NAME,
     GENDER,
     IMAGE,
-    WIKI
+    WIKI,
+    RANK,
+    ATTRIBUTES,
+    LINKS
 }
 
 /// Return value of the given key.
@@ -57,7 +139,10 @@ pub fn get(data: String, key: Key) -> Result<std::string::String, StarByFaceErro
                 Key::NAME => Ok(json["name"].to_string()),
                 Key::GENDER => Ok(json["gender"].to_string()),
                 Key::IMAGE => Ok(json["image"].to_string()),
-                Key::WIKI => Ok(json["wiki"].to_string())
+                Key::WIKI => Ok(json["wiki"].to_string()),
+                Key::RANK => Ok(json["rank"].to_string()),
+                Key::ATTRIBUTES => Ok(json["attributes"].to_string()),
+                Key::LINKS => Ok(json["links"].to_string())
             }
         },
         Err(_) => Err(StarByFaceError::Null(String::from("null")))
@@ -75,7 +160,10 @@ pub fn get_list(data: Vec<String>, key: Key) -> Result<Vec<std::string::String>,
                     Key::NAME => list.push(json["name"].to_string()),
                     Key::GENDER => list.push(json["gender"].to_string()),
                     Key::IMAGE => list.push(json["image"].to_string()),
-                    Key::WIKI => list.push(json["wiki"].to_string())
+                    Key::WIKI => list.push(json["wiki"].to_string()),
+                    Key::RANK => list.push(json["rank"].to_string()),
+                    Key::ATTRIBUTES => list.push(json["attributes"].to_string()),
+                    Key::LINKS => list.push(json["links"].to_string())
                 }
             },
             Err(_) => return Err(StarByFaceError::Null(String::from("null")))
@@ -0,0 +1,122 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::{Celebrity, Client, Diagnostics, Gender, Preprocessing, Source, StarByFaceError};
+use crate::page::Page;
+
+/// Result of looking up one image.
+///
+/// `StarByFace` and `StarByFaceFile` are built on it.
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    #[doc(hidden)]
+    response: Result<Page, StarByFaceError>,
+    #[doc(hidden)]
+    preprocessing: Option<Preprocessing>
+}
+
+impl Lookup {
+
+    /// `source` Image to look up.
+    ///
+    /// `client` Client used to send the request.
+    pub fn new(source: &Source, client: &Client) -> Self {
+        let (response, preprocessing) = client.lookup(source);
+        Self {
+            response,
+            preprocessing
+        }
+    }
+
+    #[doc(hidden)]
+    fn page(&self) -> Result<&Page, StarByFaceError> {
+        match self.response {
+            Ok(ref page) => Ok(page),
+            Err(ref err) => Err(err.clone())
+        }
+    }
+
+    /// Return warning message.
+    pub fn warning(&self) -> Option<std::string::String> {
+        match self.response {
+            Ok(ref page) => page.warning(),
+            Err(_) => None
+        }
+    }
+
+    /// Return parser diagnostics.
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self.response {
+            Ok(ref page) => Some(page.diagnostics()),
+            Err(_) => None
+        }
+    }
+
+    /// Return changes made to the image before it was
+    /// uploaded, `None` for image URIs or if neither
+    /// metadata stripping nor a preprocessor is enabled.
+    pub fn preprocessing(&self) -> Option<&Preprocessing> {
+        self.preprocessing.as_ref()
+    }
+
+    /// Return raw response body.
+    pub fn raw_body(&self) -> Option<&str> {
+        match self.response {
+            Ok(ref page) => Some(page.body()),
+            Err(_) => None
+        }
+    }
+
+    /// Return vector of celebrities.
+    pub fn celebrities(&self) -> Result<Vec<Celebrity>, StarByFaceError> {
+        self.page()?.celebrities()
+    }
+
+    /// Return vector of JSON Objects.
+    pub fn get_data(&self) -> Result<Vec<std::string::String>, StarByFaceError>  {
+        self.page()?.get_data()
+    }
+
+    /// `result` Gender enum.
+    ///
+    /// Return vector of JSON Objects.
+    pub fn get_data_by_gender(&self,result: Gender) -> Result<Vec<std::string::String>, StarByFaceError>  {
+        self.page()?.get_data_by_gender(result)
+    }
+
+    /// Return vector of JSON Objects.
+    pub fn highest_similarity(&self) -> Result<Vec<std::string::String>, StarByFaceError> {
+        self.page()?.highest_similarity()
+    }
+
+    /// `result` Gender enum.
+    ///
+    /// Return JSON Object.
+    pub fn highest_similarity_by_gender(&self,result: Gender) -> Result<std::string::String, StarByFaceError> {
+        self.page()?.highest_similarity_by_gender(result)
+    }
+
+    /// Return vector of JSON Objects.
+    pub fn lowest_similarity(&self) -> Result<Vec<std::string::String>, StarByFaceError> {
+        self.page()?.lowest_similarity()
+    }
+
+    /// `result` Gender enum.
+    ///
+    /// Return JSON Object.
+    pub fn lowest_similarity_by_gender(&self,result: Gender) -> Result<std::string::String, StarByFaceError> {
+        self.page()?.lowest_similarity_by_gender(result)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,378 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::StarByFaceError;
+
+#[doc(hidden)]
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[doc(hidden)]
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+#[doc(hidden)]
+const XMP_HEADERS: [&[u8]; 2] = [
+    b"http://ns.adobe.com/xap/1.0/\0",
+    b"http://ns.adobe.com/xmp/extension/\0"
+];
+
+#[doc(hidden)]
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+/// `data` Image content.
+///
+/// Return the image without its EXIF, XMP, IPTC, comment and
+/// text metadata, and the names of the metadata removed.
+///
+/// JPEG, PNG, WebP and GIF images are rewritten without
+/// decoding their pixels. The EXIF orientation of JPEG, PNG
+/// and WebP images is kept in an EXIF structure holding
+/// nothing else.
+///
+/// Return `InvalidImage` for malformed images. Other formats
+/// are returned unchanged, including TIFF, HEIC and AVIF
+/// images, whose metadata can't be stripped.
+pub fn strip_metadata(data: &[u8]) -> Result<(Vec<u8>, Vec<String>), StarByFaceError> {
+    let stripped = if data.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(data)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        strip_png(data)
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        strip_webp(data)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        strip_gif(data)
+    } else {
+        if let Some(format) = unsupported_format(data) {
+            tracing::warn!(format, "metadata can't be stripped, image uploaded unchanged");
+        }
+        Some((data.to_vec(), vec![]))
+    };
+    match stripped {
+        Some(stripped) => Ok(stripped),
+        None => Err(StarByFaceError::InvalidImage(String::from("malformed image, failed to strip its metadata")))
+    }
+}
+
+/// Return the name of the format if it may hold metadata that
+/// can't be stripped.
+#[doc(hidden)]
+fn unsupported_format(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        return Some("TIFF")
+    }
+    if data.get(4..8) != Some(b"ftyp") {
+        return None
+    }
+    match data.get(8..12)? {
+        b"heic" | b"heix" | b"hevc" | b"heim" | b"heis" | b"mif1" | b"msf1" => Some("HEIC"),
+        b"avif" | b"avis" => Some("AVIF"),
+        _ => None
+    }
+}
+
+#[doc(hidden)]
+fn removed(names: &mut Vec<String>, name: &str) {
+    if !names.iter().any(|removed| removed == name) {
+        names.push(name.to_string())
+    }
+}
+
+#[doc(hidden)]
+fn strip_jpeg(data: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let mut segments = vec![];
+    let mut names = vec![];
+    let mut orientation = None;
+    let mut pos = 2;
+    while pos < data.len() {
+        if data[pos] != 0xFF {
+            return None
+        }
+        while *data.get(pos + 1)? == 0xFF {
+            pos += 1
+        }
+        let marker = data[pos + 1];
+        if marker == 0xD9 {
+            segments.push(&data[pos..pos + 2]);
+            if pos + 2 < data.len() {
+                removed(&mut names, "trailing data");
+            }
+            break
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            segments.push(&data[pos..pos + 2]);
+            pos += 2;
+            continue
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if length < 2 {
+            return None
+        }
+        let segment = data.get(pos..pos + 2 + length)?;
+        let payload = &segment[4..];
+        pos += 2 + length;
+        let name = match marker {
+            0xE1 if payload.starts_with(EXIF_HEADER) => {
+                orientation = exif_orientation(&payload[EXIF_HEADER.len()..]).or(orientation);
+                Some("EXIF")
+            },
+            0xE1 if XMP_HEADERS.iter().any(|header| payload.starts_with(header)) => Some("XMP"),
+            0xE2 if payload.starts_with(ICC_HEADER) => None,
+            0xED => Some("IPTC"),
+            0xFE => Some("comment"),
+            0xE0 | 0xEE => None,
+            0xE1..=0xEF => Some("application data"),
+            _ => None
+        };
+        match name {
+            Some(name) => removed(&mut names, name),
+            None => segments.push(segment)
+        }
+        if marker == 0xDA {
+            let start = pos;
+            while pos < data.len() {
+                if data[pos] == 0xFF {
+                    match data.get(pos + 1) {
+                        Some(0x00 | 0xFF | 0xD0..=0xD7) => {},
+                        _ => break
+                    }
+                }
+                pos += 1
+            }
+            segments.push(&data[start..pos]);
+        }
+    }
+    let exif = orientation.filter(|orientation| *orientation != 1).map(orientation_segment);
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    let mut segments = segments.into_iter().peekable();
+    if let Some(exif) = exif {
+        if segments.peek().map(|segment| segment.starts_with(&[0xFF, 0xE0])).unwrap_or(false) {
+            output.extend_from_slice(segments.next()?);
+        }
+        output.extend_from_slice(&exif);
+    }
+    for segment in segments {
+        output.extend_from_slice(segment);
+    }
+    Some((output, names))
+}
+
+/// Return the orientation tag of the first IFD of an EXIF
+/// TIFF structure.
+#[doc(hidden)]
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let tiff = tiff.strip_prefix(EXIF_HEADER).unwrap_or(tiff);
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?, *tiff.get(offset + 2)?, *tiff.get(offset + 3)?];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    let ifd = u32_at(4)? as usize;
+    for entry in 0..u16_at(ifd)? as usize {
+        let offset = ifd + 2 + entry * 12;
+        if u16_at(offset)? == 0x0112 {
+            return u16_at(offset + 8).filter(|orientation| (1..=8).contains(orientation))
+        }
+    }
+    None
+}
+
+/// Return an EXIF TIFF structure with the orientation tag only.
+#[doc(hidden)]
+fn orientation_tiff(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\x00\x2A\x00\x00\x00\x08".to_vec();
+    tiff.extend_from_slice(&[0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    tiff
+}
+
+/// Return an APP1 segment holding an EXIF structure with
+/// the orientation tag only.
+#[doc(hidden)]
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut segment = vec![0xFF, 0xE1, 0x00, 0x22];
+    segment.extend_from_slice(EXIF_HEADER);
+    segment.extend_from_slice(&orientation_tiff(orientation));
+    segment
+}
+
+/// Return the CRC-32 of a PNG chunk type and data.
+#[doc(hidden)]
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[doc(hidden)]
+fn strip_png(data: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut names = vec![];
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk = data.get(pos..pos.checked_add(12 + length)?)?;
+        let kind = &chunk[4..8];
+        pos += chunk.len();
+        let name = match kind {
+            b"eXIf" => {
+                if let Some(orientation) = exif_orientation(&chunk[8..chunk.len() - 4]).filter(|orientation| *orientation != 1) {
+                    let mut exif = b"eXIf".to_vec();
+                    exif.extend_from_slice(&orientation_tiff(orientation));
+                    output.extend_from_slice(&((exif.len() - 4) as u32).to_be_bytes());
+                    output.extend_from_slice(&exif);
+                    output.extend_from_slice(&crc32(&exif).to_be_bytes());
+                }
+                Some("EXIF")
+            },
+            b"iTXt" if chunk[8..].starts_with(b"XML:com.adobe.xmp\0") => Some("XMP"),
+            b"tEXt" | b"zTXt" | b"iTXt" => Some("text"),
+            b"tIME" => Some("time"),
+            _ => None
+        };
+        match name {
+            Some(name) => removed(&mut names, name),
+            None => output.extend_from_slice(chunk)
+        }
+        if kind == b"IEND" {
+            if pos < data.len() {
+                removed(&mut names, "trailing data");
+            }
+            break
+        }
+    }
+    Some((output, names))
+}
+
+#[doc(hidden)]
+fn strip_webp(data: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let size = u32::from_le_bytes(data[4..8].try_into().ok()?) as usize;
+    let end = 8usize.checked_add(size)?;
+    let mut output = data[..12].to_vec();
+    let mut names = vec![];
+    let mut flags = None;
+    let mut orientation_kept = false;
+    let mut pos = 12;
+    while pos < end {
+        let length = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let padded = length.checked_add(length % 2)?;
+        let chunk = data.get(pos..pos.checked_add(8 + padded)?)?;
+        pos += chunk.len();
+        match &chunk[..4] {
+            b"EXIF" => {
+                if let Some(orientation) = exif_orientation(&chunk[8..8 + length]).filter(|orientation| *orientation != 1) {
+                    let tiff = orientation_tiff(orientation);
+                    output.extend_from_slice(b"EXIF");
+                    output.extend_from_slice(&(tiff.len() as u32).to_le_bytes());
+                    output.extend_from_slice(&tiff);
+                    orientation_kept = true;
+                }
+                removed(&mut names, "EXIF")
+            },
+            b"XMP " => removed(&mut names, "XMP"),
+            b"VP8X" if length >= 1 => {
+                flags = Some(output.len() + 8);
+                output.extend_from_slice(chunk);
+            },
+            _ => output.extend_from_slice(chunk)
+        }
+    }
+    if let Some(flags) = flags {
+        output[flags] &= if orientation_kept { !0x04 } else { !0x0C };
+    }
+    if end < data.len() {
+        removed(&mut names, "trailing data");
+    }
+    let size = u32::try_from(output.len() - 8).ok()?;
+    output[4..8].copy_from_slice(&size.to_le_bytes());
+    Some((output, names))
+}
+
+#[doc(hidden)]
+fn strip_gif(data: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let flags = *data.get(10)?;
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 0x07) + 1);
+    }
+    let mut output = data.get(..pos)?.to_vec();
+    let mut names = vec![];
+    loop {
+        match *data.get(pos)? {
+            0x3B => {
+                output.push(0x3B);
+                if pos + 1 < data.len() {
+                    removed(&mut names, "trailing data");
+                }
+                break
+            },
+            0x2C => {
+                let flags = *data.get(pos + 9)?;
+                let mut start = pos + 10;
+                if flags & 0x80 != 0 {
+                    start += 3 << ((flags & 0x07) + 1);
+                }
+                let end = sub_blocks(data, start + 1)?;
+                output.extend_from_slice(&data[pos..end]);
+                pos = end;
+            },
+            0x21 => {
+                let end = sub_blocks(data, pos + 2)?;
+                let name = match *data.get(pos + 1)? {
+                    0xFE => Some("comment"),
+                    0xFF => match data.get(pos + 3..pos + 14)? {
+                        b"XMP DataXMP" => Some("XMP"),
+                        b"NETSCAPE2.0" | b"ANIMEXTS1.0" => None,
+                        _ => Some("application data")
+                    },
+                    _ => None
+                };
+                match name {
+                    Some(name) => removed(&mut names, name),
+                    None => output.extend_from_slice(&data[pos..end])
+                }
+                pos = end;
+            },
+            _ => return None
+        }
+    }
+    Some((output, names))
+}
+
+/// Return the position after the data sub-blocks starting at
+/// `pos`, ended by an empty block.
+#[doc(hidden)]
+fn sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let size = *data.get(pos)? as usize;
+        pos += 1 + size;
+        if size == 0 {
+            return Some(pos)
+        }
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::BTreeMap;
+use crate::{Batch, BoundingBox, Client, FaceDetector, Lookup, Source, StarByFaceError};
+use crate::preprocess::{decode, encode};
+
+/// Looks up every face of an image separately.
+///
+/// Each face is cropped with some padding around it and
+/// uploaded as its own image.
+#[derive(Debug, Clone)]
+pub struct MultiFace {
+    #[doc(hidden)]
+    client: Client,
+    #[doc(hidden)]
+    detector: FaceDetector,
+    #[doc(hidden)]
+    padding: f64,
+    #[doc(hidden)]
+    max_faces: Option<usize>,
+    #[doc(hidden)]
+    concurrency: usize
+}
+
+impl MultiFace {
+
+    /// `client` Client used to send the requests.
+    ///
+    /// `detector` Detector used to find the faces.
+    pub fn new(client: &Client, detector: &FaceDetector) -> Self {
+        Self {
+            client: client.without_face_detector(),
+            detector: detector.clone(),
+            padding: 0.5,
+            max_faces: None,
+            concurrency: 4
+        }
+    }
+
+    /// `padding` Margin added on each side of a face, as a
+    /// fraction of its size, 0.5 by default.
+    pub fn with_padding(mut self, padding: f64) -> Self {
+        self.padding = padding.max(0.0);
+        self
+    }
+
+    /// `max_faces` Largest number of faces looked up, highest
+    /// detection scores first, `None` for every face.
+    pub fn with_max_faces(mut self, max_faces: Option<usize>) -> Self {
+        self.max_faces = max_faces;
+        self
+    }
+
+    /// `concurrency` Number of lookups running at once, 4 by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// `source` Image file or bytes.
+    ///
+    /// Return the lookup of each face keyed by its bounding box
+    /// in the image with its EXIF orientation applied.
+    pub fn run(&self, source: &Source) -> Result<BTreeMap<BoundingBox, Lookup>, StarByFaceError> {
+        let crops = self.crops(source)?;
+        let sources: Vec<Source> = crops.iter().map(|(_, source)| source.clone()).collect();
+        let lookups = Batch::new(&self.client).with_concurrency(self.concurrency).run(&sources);
+        Ok(crops.into_iter().map(|(bounding_box, _)| bounding_box).zip(lookups).collect())
+    }
+
+    /// Return the bounding box and the crop of each face.
+    #[doc(hidden)]
+    fn crops(&self, source: &Source) -> Result<Vec<(BoundingBox, Source)>, StarByFaceError> {
+        if let Source::Url(_) = *source {
+            return Err(StarByFaceError::Error(String::from("Faces can only be detected in files and bytes")))
+        }
+        let (data, file_name) = source.read()?;
+        let image = decode(&data)?;
+        let mut faces = self.detector.detect_image(&image);
+        if let Some(max_faces) = self.max_faces {
+            faces.truncate(max_faces);
+        }
+        tracing::info!(faces = faces.len(), "detected faces");
+        if faces.is_empty() {
+            return Err(StarByFaceError::NoFace)
+        }
+        let stem = match file_name.rsplit_once('.') {
+            Some((stem, _)) => stem.to_string(),
+            None => file_name
+        };
+        let mut crops = vec![];
+        for face in &faces {
+            let (left, top, width, height) = match region(face.bounding_box, self.padding, image.width(), image.height()) {
+                Some(region) => region,
+                None => {
+                    tracing::debug!(bounding_box = ?face.bounding_box, "skipped empty face");
+                    continue
+                }
+            };
+            let crop = image.crop_imm(left, top, width, height);
+            crops.push((face.bounding_box, Source::Bytes {
+                data: encode(&crop)?,
+                file_name: format!("{}-face-{}.jpg", stem, crops.len() + 1)
+            }));
+        }
+        if crops.is_empty() {
+            return Err(StarByFaceError::NoFace)
+        }
+        Ok(crops)
+    }
+}
+
+/// Return the left, top, width and height of the crop of a face
+/// with `padding` around it, clamped to the image, or `None` if
+/// the face or the crop is empty.
+#[doc(hidden)]
+fn region(bounding_box: BoundingBox, padding: f64, image_width: u32, image_height: u32) -> Option<(u32, u32, u32, u32)> {
+    let BoundingBox { x, y, width, height } = bounding_box;
+    if width == 0 || height == 0 || x >= image_width || y >= image_height {
+        return None
+    }
+    let margin_x = (width as f64 * padding) as u32;
+    let margin_y = (height as f64 * padding) as u32;
+    let left = x.saturating_sub(margin_x);
+    let top = y.saturating_sub(margin_y);
+    let right = x.saturating_add(width).saturating_add(margin_x).min(image_width);
+    let bottom = y.saturating_add(height).saturating_add(margin_y).min(image_height);
+    if right <= left || bottom <= top {
+        return None
+    }
+    Some((left, top, right - left, bottom - top))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BoundingBox;
+    use super::region;
+
+    fn bounding_box(x: u32, y: u32, width: u32, height: u32) -> BoundingBox {
+        BoundingBox { x, y, width, height }
+    }
+
+    #[test]
+    fn pads_faces() {
+        assert_eq!(region(bounding_box(40, 40, 20, 10), 0.5, 100, 100), Some((30, 35, 40, 20)));
+        assert_eq!(region(bounding_box(40, 40, 20, 10), 0.0, 100, 100), Some((40, 40, 20, 10)));
+    }
+
+    #[test]
+    fn clamps_padding_to_the_image() {
+        assert_eq!(region(bounding_box(0, 5, 20, 20), 0.5, 25, 30), Some((0, 0, 25, 30)));
+    }
+
+    #[test]
+    fn skips_empty_faces() {
+        assert_eq!(region(bounding_box(10, 10, 0, 20), 0.5, 100, 100), None);
+        assert_eq!(region(bounding_box(10, 10, 20, 0), 0.5, 100, 100), None);
+        assert_eq!(region(bounding_box(100, 10, 20, 20), 0.5, 100, 100), None);
+        assert_eq!(region(bounding_box(10, 100, 20, 20), 0.0, 100, 100), None);
+        assert_eq!(region(bounding_box(u32::MAX, 10, 20, 20), 0.5, 100, 100), None);
+    }
+}
@@ -0,0 +1,391 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use crate::{Celebrity, Gender, StarByFaceError};
+
+#[doc(hidden)]
+const CARD_REGEX: &str = r#"<div\s([^>]*?\bsimilarity="[^"]*"[^>]*)>"#;
+
+#[doc(hidden)]
+const IMAGE_REGEX: &str = r#"<img\s[^>]*?\bsrc="(.*?)""#;
+
+#[doc(hidden)]
+const NAME_REGEX: &str = r#"(?s)<p(?:\s[^>]*)?>\s*(.*?)\s*</p>"#;
+
+#[doc(hidden)]
+const SECTION_REGEX: &str = r#"<div id="([\w-]+)-celebs-result">"#;
+
+#[doc(hidden)]
+const ATTRIBUTE_REGEX: &str = r#"([\w:-]+)="(.*?)""#;
+
+#[doc(hidden)]
+const LINK_REGEX: &str = r#"href="(.*?)""#;
+
+/// Structural anchors found while parsing a response.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// `<div id="male-celebs-result">` was found.
+    pub male_section: bool,
+    /// `<div id="female-celebs-result">` was found.
+    pub female_section: bool,
+    /// Ids of every `<div id="...-celebs-result">` found.
+    pub sections: Vec<String>,
+    /// Number of `similarity="..."` attributes.
+    pub similarity_attributes: usize,
+    /// Number of parsed cards.
+    pub cards: usize,
+    /// Length of the response body in bytes.
+    pub body_length: usize,
+    /// File the response body was dumped to.
+    pub dump: Option<PathBuf>
+}
+
+impl Diagnostics {
+
+    /// Return true if the response looks like a results page.
+    pub fn is_results_page(&self) -> bool {
+        !self.sections.is_empty() || self.similarity_attributes > 0
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "male section: {}, female section: {}, sections: [{}], similarity attributes: {}, cards: {}, body length: {}",
+               self.male_section,
+               self.female_section,
+               self.sections.join(", "),
+               self.similarity_attributes,
+               self.cards,
+               self.body_length)?;
+        if let Some(dump) = &self.dump {
+            write!(f, ", dump: {}",dump.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub(crate) struct Page {
+    body: String,
+    sections: Vec<(Gender, Vec<Celebrity>)>,
+    warning: Option<String>,
+    error_message: Option<String>,
+    diagnostics: Diagnostics
+}
+
+impl Page {
+    pub(crate) fn parse(body: String) -> Self {
+        let sections = sections(&body);
+        let diagnostics = Diagnostics {
+            male_section: sections.iter().any(|(gender, _)| gender == &Gender::MALE),
+            female_section: sections.iter().any(|(gender, _)| gender == &Gender::FEMALE),
+            sections: sections.iter().map(|(gender, _)| format!("{}-celebs-result", gender)).collect(),
+            similarity_attributes: body.matches("similarity=\"").count(),
+            cards: sections.iter().map(|(_, cards)| cards.len()).sum(),
+            body_length: body.len(),
+            dump: None
+        };
+        tracing::debug!(sections = ?diagnostics.sections,
+            similarity_attributes = diagnostics.similarity_attributes,
+            cards = diagnostics.cards,
+            "parsed response");
+        Self {
+            warning: warning(&body),
+            error_message: error_message(&body),
+            body,
+            sections,
+            diagnostics
+        }
+    }
+
+    /// Write the body to `dump_dir` if the markup changed.
+    pub(crate) fn dump(mut self, dump_dir: Option<&Path>) -> Self {
+        if self.is_markup_changed() {
+            tracing::warn!(diagnostics = %self.diagnostics, "markup changed");
+        }
+        if let Some(dir) = dump_dir {
+            if self.is_markup_changed() {
+                let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                let path = dir.join(format!("starbyface-{}.html", millis));
+                if std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &self.body)).is_ok() {
+                    self.diagnostics.dump = Some(path)
+                }
+            }
+        }
+        self
+    }
+
+    pub(crate) fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub(crate) fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub(crate) fn warning(&self) -> Option<std::string::String> {
+        self.warning.clone()
+    }
+
+    #[doc(hidden)]
+    fn is_markup_changed(&self) -> bool {
+        self.diagnostics.is_results_page()
+            && self.diagnostics.cards == 0
+            && self.warning.is_none()
+            && self.error_message.is_none()
+    }
+
+    #[doc(hidden)]
+    fn is_error(&self) -> Option<StarByFaceError> {
+        let error = match self.error_message.clone() {
+            Some(message) => Some(StarByFaceError::Error(message)),
+            None => if self.is_markup_changed() {
+                Some(StarByFaceError::MarkupChanged(self.diagnostics.clone()))
+            } else {
+                None
+            }
+        };
+        if let Some(ref error) = error {
+            tracing::debug!(error = %error, "query failed");
+        }
+        error
+    }
+
+    /// Return `ok`, or the kind of the error queries return.
+    pub(crate) fn outcome(&self) -> &'static str {
+        match self.is_error() {
+            Some(err) => err.kind(),
+            None => if self.diagnostics.cards == 0 {
+                "null"
+            } else {
+                "ok"
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    fn section(&self, gender: &Gender) -> &[Celebrity] {
+        match self.sections.iter().find(|(i, _)| i == gender) {
+            Some((_, cards)) => cards,
+            None => &[]
+        }
+    }
+
+    pub(crate) fn celebrities(&self) -> Result<Vec<Celebrity>, StarByFaceError> {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        let list: Vec<Celebrity> = self.sections.iter()
+            .flat_map(|(_, cards)| cards.iter().cloned())
+            .collect();
+        if list.is_empty() {
+            Err(StarByFaceError::Null(String::from("null")))
+        } else {
+            Ok(list)
+        }
+    }
+
+    pub(crate) fn get_data(&self) -> Result<Vec<std::string::String>, StarByFaceError>  {
+        Ok(self.celebrities()?.iter().map(Celebrity::to_json).collect())
+    }
+
+    pub(crate) fn get_data_by_gender(&self,result: Gender) -> Result<Vec<std::string::String>, StarByFaceError>  {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        let cards = self.section(&result);
+        if !cards.is_empty() {
+            Ok(cards.iter().map(Celebrity::to_json).collect())
+        } else {
+            Err(StarByFaceError::Null(String::from("null")))
+        }
+    }
+
+    pub(crate) fn highest_similarity(&self) -> Result<Vec<std::string::String>, StarByFaceError> {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        let list: Vec<std::string::String> = self.sections.iter()
+            .filter_map(|(_, cards)| cards.first())
+            .map(Celebrity::to_json)
+            .collect();
+        if !list.is_empty() {
+            Ok(list)
+        } else {
+            Err(StarByFaceError::Null(String::from("null")))
+        }
+    }
+
+    pub(crate) fn highest_similarity_by_gender(&self,result: Gender) -> Result<std::string::String, StarByFaceError> {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        match self.section(&result).first() {
+            Some(data) => Ok(data.to_json()),
+            None => Err(StarByFaceError::Null(String::from("null")))
+        }
+    }
+
+    pub(crate) fn lowest_similarity(&self) -> Result<Vec<std::string::String>, StarByFaceError> {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        let list: Vec<std::string::String> = self.sections.iter()
+            .filter_map(|(_, cards)| cards.last())
+            .map(Celebrity::to_json)
+            .collect();
+        if !list.is_empty() {
+            Ok(list)
+        } else {
+            Err(StarByFaceError::Null(String::from("null")))
+        }
+    }
+
+    pub(crate) fn lowest_similarity_by_gender(&self,result: Gender) -> Result<std::string::String, StarByFaceError> {
+        if let Some(error) = self.is_error() {
+            return Err(error)
+        }
+        match self.section(&result).last() {
+            Some(data) => Ok(data.to_json()),
+            None => Err(StarByFaceError::Null(String::from("null")))
+        }
+    }
+}
+
+/// Return true if the body has a result section or a card.
+#[doc(hidden)]
+pub(crate) fn has_results(body: &str) -> bool {
+    body.contains("similarity=\"") || match Regex::new(SECTION_REGEX) {
+        Ok(regex) => regex.is_match(body),
+        Err(_) => false
+    }
+}
+
+#[doc(hidden)]
+fn sections(body: &str) -> Vec<(Gender, Vec<Celebrity>)> {
+    let regex = match Regex::new(SECTION_REGEX) {
+        Ok(regex) => regex,
+        Err(_) => return vec![]
+    };
+    let anchors: Vec<(usize, Gender)> = regex.captures_iter(body)
+        .filter_map(|cap| cap.get(0).map(|data| (data.start(), Gender::from_section(&cap[1]))))
+        .collect();
+    let mut list: Vec<(Gender, Vec<Celebrity>)> = vec![];
+    for (index, (start, gender)) in anchors.iter().enumerate() {
+        let end = match anchors.get(index + 1) {
+            Some((end, _)) => *end,
+            None => body.len()
+        };
+        let cards = section(&body[*start..end], gender);
+        match list.iter_mut().find(|(i, _)| i == gender) {
+            Some((_, list)) => {
+                let rank = list.len();
+                list.extend(cards.into_iter().map(|mut card| {
+                    card.rank += rank;
+                    card
+                }))
+            },
+            None => list.push((gender.clone(), cards))
+        }
+    }
+    list.sort_by_key(|(gender, _)| match gender {
+        Gender::FEMALE => 0,
+        Gender::MALE => 1,
+        Gender::Other(_) => 2
+    });
+    list
+}
+
+/// Return the cards of a section. A card runs from its element,
+/// the `<div>` with a `similarity` attribute, to the next one.
+#[doc(hidden)]
+fn section(data: &str, gender: &Gender) -> Vec<Celebrity> {
+    match (Regex::new(CARD_REGEX), Regex::new(ATTRIBUTE_REGEX), Regex::new(LINK_REGEX), Regex::new(IMAGE_REGEX), Regex::new(NAME_REGEX)) {
+        (Ok(regex), Ok(attribute_regex), Ok(link_regex), Ok(image_regex), Ok(name_regex)) => {
+            let tags: Vec<(usize, &str)> = regex.captures_iter(data)
+                .filter_map(|cap| cap.get(0).zip(cap.get(1)).map(|(card, tag)| (card.start(), tag.as_str())))
+                .collect();
+            let mut list = vec![];
+            for (index, &(start, tag)) in tags.iter().enumerate() {
+                let end = match tags.get(index + 1) {
+                    Some(&(end, _)) => end,
+                    None => data.len()
+                };
+                let card = &data[start..end];
+                let (image, name) = match (image_regex.captures(card), name_regex.captures(card)) {
+                    (Some(image), Some(name)) => (image[1].replace(" ","%20"), name[1].to_string()),
+                    _ => continue
+                };
+                let attributes: BTreeMap<String, String> = attribute_regex.captures_iter(tag)
+                    .map(|attribute| (attribute[1].to_string(), attribute[2].to_string()))
+                    .collect();
+                let mut links: Vec<String> = link_regex.captures_iter(card)
+                    .map(|link| link[1].to_string())
+                    .collect();
+                let wiki = match links.iter().find(|link| link.contains("wikipedia.org")) {
+                    Some(wiki) => wiki.clone(),
+                    None => links.first().cloned().unwrap_or_default()
+                };
+                links.retain(|link| link != &wiki);
+                list.push(Celebrity {
+                    rank: list.len() + 1,
+                    similarity: attributes.get("similarity")
+                        .and_then(|similarity| similarity.trim().trim_end_matches('%').parse().ok())
+                        .unwrap_or_default(),
+                    name,
+                    gender: gender.clone(),
+                    image,
+                    wiki,
+                    attributes,
+                    links
+                })
+            }
+            list
+        },
+        _ => vec![]
+    }
+}
+
+#[doc(hidden)]
+fn warning(body: &str) -> Option<std::string::String> {
+    match Regex::new(r#"showError(.*?);"#) {
+        Ok(regex) => match regex.captures(body) {
+            Some(cap) => cap.get(1).map(|data| data.as_str().replace("(","").replace(")","").replace("\"","")),
+            None => None
+        },
+        Err(_) => None
+    }
+}
+
+#[doc(hidden)]
+fn error_message(body: &str) -> Option<std::string::String> {
+    match Regex::new(r#""errorMsg":"(.*?)""#) {
+        Ok(regex) => match regex.captures(body) {
+            Some(cap) => cap.get(1).map(|data| if data.as_str().contains("Parameter is not valid") {
+                "Invalid Image".to_string()
+            } else {
+                data.as_str().to_string()
+            }),
+            None => None
+        },
+        Err(_) => None
+    }
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::fmt::{Display, Formatter};
+use crate::Quality;
+use crate::source::mime_type;
+#[cfg(feature = "face")]
+use crate::Alignment;
+
+/// Changes made to an image before it was uploaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preprocessing {
+    /// Format detected from the content, `None` if unknown.
+    pub format: Option<String>,
+    /// Length of the image before the changes, in bytes.
+    pub original_length: usize,
+    /// Length of the uploaded image, in bytes.
+    pub length: usize,
+    /// EXIF orientation applied to the pixels, from 2 to 8.
+    pub orientation: Option<u8>,
+    /// Dimensions before and after downscaling.
+    pub resized: Option<((u32, u32), (u32, u32))>,
+    /// Format the image was converted to JPEG from.
+    pub converted: Option<String>,
+    /// Names of the metadata removed, such as `EXIF` or `XMP`.
+    pub metadata_removed: Vec<String>,
+    /// Quality scores of the uploaded image.
+    pub quality: Option<Quality>,
+    /// Number of faces found by the face detector.
+    pub faces: Option<usize>,
+    /// Face the uploaded crop was aligned on.
+    #[cfg(feature = "face")]
+    pub alignment: Option<Alignment>
+}
+
+impl Preprocessing {
+
+    /// Return an empty report of the image.
+    #[doc(hidden)]
+    pub(crate) fn of(data: &[u8]) -> Self {
+        Self {
+            format: mime_type(data).strip_prefix("image/").map(String::from),
+            original_length: data.len(),
+            length: data.len(),
+            ..Self::default()
+        }
+    }
+
+    /// Return true if the uploaded image differs from the original.
+    pub fn is_changed(&self) -> bool {
+        #[cfg(feature = "face")]
+        if self.alignment.is_some() {
+            return true
+        }
+        self.orientation.is_some() || self.resized.is_some() || self.converted.is_some() || !self.metadata_removed.is_empty()
+    }
+}
+
+impl Display for Preprocessing {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "format: {}, length: {} -> {}",
+               self.format.as_deref().unwrap_or("unknown"),
+               self.original_length,
+               self.length)?;
+        if let Some(orientation) = self.orientation {
+            write!(f, ", orientation: {}",orientation)?;
+        }
+        if let Some(((width, height), (new_width, new_height))) = self.resized {
+            write!(f, ", resized: {}x{} -> {}x{}",width,height,new_width,new_height)?;
+        }
+        if let Some(converted) = &self.converted {
+            write!(f, ", converted: {} -> jpeg",converted)?;
+        }
+        if !self.metadata_removed.is_empty() {
+            write!(f, ", metadata removed: {}",self.metadata_removed.join(", "))?;
+        }
+        if let Some(quality) = &self.quality {
+            write!(f, ", quality: {}",quality)?;
+        }
+        if let Some(faces) = self.faces {
+            write!(f, ", faces: {}",faces)?;
+        }
+        #[cfg(feature = "face")]
+        if let Some(alignment) = &self.alignment {
+            write!(f, ", aligned: {}",alignment)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "image")]
+pub use self::preprocessor::Preprocessor;
+#[cfg(feature = "image")]
+pub(crate) use self::preprocessor::{decode, encode, is_oriented};
+
+#[cfg(feature = "image")]
+mod preprocessor {
+    use std::io::Cursor;
+    use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::imageops::FilterType;
+    use image::metadata::Orientation;
+    use crate::StarByFaceError;
+    use super::Preprocessing;
+
+    #[doc(hidden)]
+    const CONVERTED_FORMATS: [ImageFormat; 4] = [
+        ImageFormat::WebP,
+        ImageFormat::Png,
+        ImageFormat::Bmp,
+        ImageFormat::Tiff
+    ];
+
+    /// Image preprocessing applied to files and bytes before
+    /// they are uploaded.
+    ///
+    /// By default the EXIF orientation is applied, images larger
+    /// than 2048 pixels are downscaled and WebP, PNG, BMP and
+    /// TIFF images are converted to JPEG at quality 90.
+    #[derive(Debug, Clone)]
+    pub struct Preprocessor {
+        #[doc(hidden)]
+        orientation: bool,
+        #[doc(hidden)]
+        max_dimension: Option<u32>,
+        #[doc(hidden)]
+        conversion: bool,
+        #[doc(hidden)]
+        quality: u8
+    }
+
+    impl Default for Preprocessor {
+        fn default() -> Self {
+            Self {
+                orientation: true,
+                max_dimension: Some(2048),
+                conversion: true,
+                quality: 90
+            }
+        }
+    }
+
+    impl Preprocessor {
+
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// `orientation` Rotate and flip the pixels according to
+        /// the EXIF orientation, true by default.
+        pub fn with_orientation(mut self, orientation: bool) -> Self {
+            self.orientation = orientation;
+            self
+        }
+
+        /// `max_dimension` Largest width or height uploaded, `None`
+        /// to keep the original dimensions.
+        pub fn with_max_dimension(mut self, max_dimension: Option<u32>) -> Self {
+            self.max_dimension = max_dimension;
+            self
+        }
+
+        /// `conversion` Convert WebP, PNG, BMP and TIFF images to
+        /// JPEG, true by default.
+        pub fn with_conversion(mut self, conversion: bool) -> Self {
+            self.conversion = conversion;
+            self
+        }
+
+        /// `quality` JPEG quality from 1 to 100, 90 by default.
+        pub fn with_quality(mut self, quality: u8) -> Self {
+            self.quality = quality.clamp(1, 100);
+            self
+        }
+
+        /// `data` Image content.
+        ///
+        /// Return the image to upload and the changes made to it.
+        /// Images of unknown format are returned unchanged.
+        pub fn process(&self, data: &[u8]) -> Result<(Vec<u8>, Preprocessing), StarByFaceError> {
+            let mut preprocessing = Preprocessing {
+                original_length: data.len(),
+                length: data.len(),
+                ..Preprocessing::default()
+            };
+            let format = match image::guess_format(data) {
+                Ok(format) => format,
+                Err(_) => return Ok((data.to_vec(), preprocessing))
+            };
+            preprocessing.format = Some(format_name(format));
+            let mut decoder = match ImageReader::with_format(Cursor::new(data), format).into_decoder() {
+                Ok(decoder) => decoder,
+                Err(err) => return Err(StarByFaceError::InvalidImage(err.to_string()))
+            };
+            let orientation = match decoder.orientation() {
+                Ok(orientation) if self.orientation => orientation,
+                _ => Orientation::NoTransforms
+            };
+            let max_dimension = self.max_dimension.unwrap_or(u32::MAX);
+            let (width, height) = decoder.dimensions();
+            let oversized = width > max_dimension || height > max_dimension;
+            let converted = self.conversion && CONVERTED_FORMATS.contains(&format);
+            if orientation == Orientation::NoTransforms && !oversized && !converted {
+                return Ok((data.to_vec(), preprocessing))
+            }
+            let mut image = match DynamicImage::from_decoder(decoder) {
+                Ok(image) => image,
+                Err(err) => return Err(StarByFaceError::InvalidImage(err.to_string()))
+            };
+            if orientation != Orientation::NoTransforms {
+                image.apply_orientation(orientation);
+                preprocessing.orientation = Some(orientation.to_exif());
+            }
+            if oversized {
+                let original = (image.width(), image.height());
+                image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+                preprocessing.resized = Some((original, (image.width(), image.height())));
+            }
+            if format != ImageFormat::Jpeg {
+                preprocessing.converted = Some(format_name(format));
+            }
+            let mut output = vec![];
+            let encoder = JpegEncoder::new_with_quality(&mut output, self.quality);
+            if let Err(err) = DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder) {
+                return Err(StarByFaceError::Error(format!("Failed to encode image: {}",err)))
+            }
+            preprocessing.length = output.len();
+            Ok((output, preprocessing))
+        }
+    }
+
+    #[doc(hidden)]
+    fn format_name(format: ImageFormat) -> String {
+        format.to_mime_type().trim_start_matches("image/").to_string()
+    }
+
+    /// Decode the image and apply its EXIF orientation.
+    #[doc(hidden)]
+    pub(crate) fn decode(data: &[u8]) -> Result<DynamicImage, StarByFaceError> {
+        let decoded = ImageReader::new(Cursor::new(data)).with_guessed_format()
+            .map_err(image::ImageError::IoError)
+            .and_then(|reader| reader.into_decoder())
+            .and_then(|mut decoder| {
+                let orientation = decoder.orientation()?;
+                let mut image = DynamicImage::from_decoder(decoder)?;
+                image.apply_orientation(orientation);
+                Ok(image)
+            });
+        match decoded {
+            Ok(image) => Ok(image),
+            Err(err) => Err(StarByFaceError::InvalidImage(err.to_string()))
+        }
+    }
+
+    /// Return true if the image has an EXIF orientation that
+    /// rotates or flips it.
+    #[doc(hidden)]
+    pub(crate) fn is_oriented(data: &[u8]) -> bool {
+        ImageReader::new(Cursor::new(data)).with_guessed_format().ok()
+            .and_then(|reader| reader.into_decoder().ok())
+            .and_then(|mut decoder| decoder.orientation().ok())
+            .is_some_and(|orientation| orientation != Orientation::NoTransforms)
+    }
+
+    /// Encode the image as JPEG.
+    #[doc(hidden)]
+    pub(crate) fn encode(image: &DynamicImage) -> Result<Vec<u8>, StarByFaceError> {
+        let mut output = vec![];
+        match DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(&mut output, 90)) {
+            Ok(_) => Ok(output),
+            Err(err) => Err(StarByFaceError::Error(format!("Failed to encode image: {}",err)))
+        }
+    }
+}
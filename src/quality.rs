@@ -0,0 +1,300 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::fmt::{Display, Formatter};
+
+/// Reason an image is unlikely to give good results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityIssue {
+    /// Shorter side below the minimum resolution.
+    LowResolution {
+        width: u32,
+        height: u32
+    },
+    /// Sharpness below the minimum.
+    Blurry(f64),
+    /// Brightness below the minimum.
+    Underexposed(f64),
+    /// Brightness above the maximum.
+    Overexposed(f64),
+    /// Largest face covers less of the image than the minimum.
+    SmallFace(f64)
+}
+
+impl Display for QualityIssue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match *self {
+            QualityIssue::LowResolution { width, height } => write!(f, "low resolution ({}x{})",width,height),
+            QualityIssue::Blurry(sharpness) => write!(f, "blurry (sharpness {:.1})",sharpness),
+            QualityIssue::Underexposed(brightness) => write!(f, "underexposed (brightness {:.2})",brightness),
+            QualityIssue::Overexposed(brightness) => write!(f, "overexposed (brightness {:.2})",brightness),
+            QualityIssue::SmallFace(face_ratio) => write!(f, "small face ({:.1}% of the image)",face_ratio * 100.0)
+        }
+    }
+}
+
+/// Quality scores of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quality {
+    /// Width with the EXIF orientation applied, in pixels.
+    pub width: u32,
+    /// Height with the EXIF orientation applied, in pixels.
+    pub height: u32,
+    /// Variance of the Laplacian of the image downscaled to
+    /// 512 pixels, lower is blurrier.
+    pub sharpness: f64,
+    /// Mean luminance from 0 for black to 1 for white.
+    pub brightness: f64,
+    /// Share of the image covered by the largest face, `None`
+    /// if no face was searched or found.
+    pub face_ratio: Option<f64>,
+    /// Scores outside of the thresholds.
+    pub issues: Vec<QualityIssue>
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}x{}, sharpness {:.1}, brightness {:.2}",
+               self.width,
+               self.height,
+               self.sharpness,
+               self.brightness)?;
+        if let Some(face_ratio) = self.face_ratio {
+            write!(f, ", face {:.1}%",face_ratio * 100.0)?;
+        }
+        for issue in &self.issues {
+            write!(f, ", {}",issue)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "image")]
+pub use self::analyzer::QualityAnalyzer;
+#[cfg(feature = "image")]
+pub(crate) use self::analyzer::sharpness;
+
+#[cfg(feature = "image")]
+mod analyzer {
+    use image::{DynamicImage, GrayImage};
+    use image::imageops::FilterType;
+    use crate::StarByFaceError;
+    use crate::preprocess::decode;
+    #[cfg(feature = "face")]
+    use crate::FaceDetector;
+    use super::{Quality, QualityIssue};
+
+    #[doc(hidden)]
+    const SHARPNESS_DIMENSION: u32 = 512;
+
+    /// Scores the resolution, sharpness, exposure and face size
+    /// of files and bytes before they are uploaded.
+    ///
+    /// By default images outside of the thresholds are uploaded
+    /// anyway and their issues are only reported.
+    #[derive(Debug, Clone)]
+    pub struct QualityAnalyzer {
+        #[doc(hidden)]
+        min_resolution: u32,
+        #[doc(hidden)]
+        min_sharpness: f64,
+        #[doc(hidden)]
+        brightness_range: (f64, f64),
+        #[cfg(feature = "face")]
+        #[doc(hidden)]
+        face_detector: Option<FaceDetector>,
+        #[cfg(feature = "face")]
+        #[doc(hidden)]
+        min_face_ratio: f64,
+        #[doc(hidden)]
+        enforcement: bool
+    }
+
+    impl Default for QualityAnalyzer {
+        fn default() -> Self {
+            Self {
+                min_resolution: 200,
+                min_sharpness: 20.0,
+                brightness_range: (0.15, 0.9),
+                #[cfg(feature = "face")]
+                face_detector: None,
+                #[cfg(feature = "face")]
+                min_face_ratio: 0.02,
+                enforcement: false
+            }
+        }
+    }
+
+    impl QualityAnalyzer {
+
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// `min_resolution` Smallest width and height, in pixels,
+        /// 200 by default.
+        pub fn with_min_resolution(mut self, min_resolution: u32) -> Self {
+            self.min_resolution = min_resolution;
+            self
+        }
+
+        /// `min_sharpness` Lowest sharpness, 20 by default.
+        pub fn with_min_sharpness(mut self, min_sharpness: f64) -> Self {
+            self.min_sharpness = min_sharpness;
+            self
+        }
+
+        /// `min` Lowest brightness, 0.15 by default.
+        ///
+        /// `max` Highest brightness, 0.9 by default.
+        pub fn with_brightness_range(mut self, min: f64, max: f64) -> Self {
+            self.brightness_range = (min, max);
+            self
+        }
+
+        /// `face_detector` Detector used to measure the largest face.
+        #[cfg(feature = "face")]
+        pub fn with_face_detector(mut self, face_detector: &FaceDetector) -> Self {
+            self.face_detector = Some(face_detector.clone());
+            self
+        }
+
+        /// `min_face_ratio` Smallest share of the image covered by
+        /// the largest face, 0.02 by default.
+        #[cfg(feature = "face")]
+        pub fn with_min_face_ratio(mut self, min_face_ratio: f64) -> Self {
+            self.min_face_ratio = min_face_ratio;
+            self
+        }
+
+        /// `enforcement` Fail lookups of images with issues with
+        /// `LowQuality`, false by default.
+        pub fn with_enforcement(mut self, enforcement: bool) -> Self {
+            self.enforcement = enforcement;
+            self
+        }
+
+        /// `data` Image content.
+        ///
+        /// Return the quality scores of the image.
+        pub fn analyze(&self, data: &[u8]) -> Result<Quality, StarByFaceError> {
+            let image = decode(data)?;
+            let (width, height) = (image.width(), image.height());
+            let gray = image.to_luma8();
+            let brightness = gray.pixels().map(|pixel| pixel[0] as f64).sum::<f64>() / (gray.len().max(1) as f64 * 255.0);
+            let sharpness = sharpness(&image);
+            #[cfg(feature = "face")]
+            let face_ratio = self.face_detector.as_ref()
+                .and_then(|face_detector| face_detector.detect_image(&image).iter()
+                    .map(|face| face.bounding_box.width as f64 * face.bounding_box.height as f64)
+                    .max_by(f64::total_cmp))
+                .map(|area| area / (width as f64 * height as f64));
+            #[cfg(not(feature = "face"))]
+            let face_ratio = None;
+            let mut issues = vec![];
+            if width.min(height) < self.min_resolution {
+                issues.push(QualityIssue::LowResolution { width, height });
+            }
+            if sharpness < self.min_sharpness {
+                issues.push(QualityIssue::Blurry(sharpness));
+            }
+            if brightness < self.brightness_range.0 {
+                issues.push(QualityIssue::Underexposed(brightness));
+            }
+            if brightness > self.brightness_range.1 {
+                issues.push(QualityIssue::Overexposed(brightness));
+            }
+            #[cfg(feature = "face")]
+            if let Some(face_ratio) = face_ratio.filter(|&face_ratio| face_ratio < self.min_face_ratio) {
+                issues.push(QualityIssue::SmallFace(face_ratio));
+            }
+            Ok(Quality { width, height, sharpness, brightness, face_ratio, issues })
+        }
+
+        /// Return the quality scores, or an error if enforced and
+        /// the image has issues.
+        #[doc(hidden)]
+        pub(crate) fn check(&self, data: &[u8]) -> Result<Quality, StarByFaceError> {
+            let quality = self.analyze(data)?;
+            if quality.issues.is_empty() {
+                Ok(quality)
+            } else if self.enforcement {
+                Err(StarByFaceError::LowQuality(quality.issues))
+            } else {
+                tracing::warn!(quality = %quality, "low quality image");
+                Ok(quality)
+            }
+        }
+    }
+
+    /// Return the sharpness of the image downscaled to 512 pixels.
+    #[doc(hidden)]
+    pub(crate) fn sharpness(image: &DynamicImage) -> f64 {
+        if image.width().max(image.height()) > SHARPNESS_DIMENSION {
+            laplacian_variance(&image.resize(SHARPNESS_DIMENSION, SHARPNESS_DIMENSION, FilterType::Triangle).to_luma8())
+        } else {
+            laplacian_variance(&image.to_luma8())
+        }
+    }
+
+    /// Return the variance of the 4-neighbour Laplacian of the
+    /// image, 0 if it is smaller than 3x3 pixels.
+    #[doc(hidden)]
+    fn laplacian_variance(gray: &GrayImage) -> f64 {
+        let (width, height) = gray.dimensions();
+        if width < 3 || height < 3 {
+            return 0.0
+        }
+        let pixel = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+        let mut sum = 0.0;
+        let mut squares = 0.0;
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let laplacian = pixel(x - 1, y) + pixel(x + 1, y) + pixel(x, y - 1) + pixel(x, y + 1) - 4.0 * pixel(x, y);
+                sum += laplacian;
+                squares += laplacian * laplacian;
+            }
+        }
+        let count = ((width - 2) * (height - 2)) as f64;
+        let mean = sum / count;
+        squares / count - mean * mean
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use image::{GrayImage, Luma};
+        use super::laplacian_variance;
+
+        #[test]
+        fn is_zero_for_uniform_and_tiny_images() {
+            assert_eq!(laplacian_variance(&GrayImage::from_pixel(10, 10, Luma([90]))), 0.0);
+            assert_eq!(laplacian_variance(&GrayImage::from_pixel(2, 10, Luma([90]))), 0.0);
+            assert_eq!(laplacian_variance(&GrayImage::from_fn(10, 2, |x, _| Luma([x as u8 * 20]))), 0.0);
+        }
+
+        #[test]
+        fn is_zero_for_linear_gradients() {
+            assert_eq!(laplacian_variance(&GrayImage::from_fn(10, 10, |x, y| Luma([(x * 10 + y * 5) as u8]))), 0.0);
+        }
+
+        #[test]
+        fn measures_stripes() {
+            let stripes = GrayImage::from_fn(10, 10, |x, _| Luma([if x % 2 == 0 { 0 } else { 100 }]));
+            assert_eq!(laplacian_variance(&stripes), 40000.0);
+            let stripes = GrayImage::from_fn(10, 10, |x, _| Luma([if x % 2 == 0 { 0 } else { 50 }]));
+            assert_eq!(laplacian_variance(&stripes), 10000.0);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Token bucket rate limiter.
+///
+/// Clones share the same bucket, so one limiter
+/// attached to a [`Client`](crate::Client) limits every
+/// lookup made through it from any thread.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    #[doc(hidden)]
+    inner: Arc<Inner>
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+struct Inner {
+    rate: f64,
+    burst: f64,
+    store: Store
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+enum Store {
+    Memory(Mutex<Bucket>),
+    File(PathBuf)
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant
+}
+
+impl RateLimiter {
+
+    /// `rate` Requests per second.
+    ///
+    /// `burst` Maximum number of requests sent back to back.
+    ///
+    /// Return an `InvalidInput` error if `rate` isn't a positive
+    /// number or `burst` is 0.
+    pub fn new(rate: f64, burst: u32) -> std::io::Result<Self> {
+        validate(rate, burst)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                rate,
+                burst: f64::from(burst),
+                store: Store::Memory(Mutex::new(Bucket {
+                    tokens: f64::from(burst),
+                    last: Instant::now()
+                }))
+            })
+        })
+    }
+
+    /// `path` State file shared by every process using the limiter.
+    ///
+    /// `rate` Requests per second.
+    ///
+    /// `burst` Maximum number of requests sent back to back.
+    ///
+    /// The bucket is kept in `path` and guarded by an exclusive
+    /// file lock, so CLI processes on one host share a single budget.
+    ///
+    /// Return an `InvalidInput` error if `rate` isn't a positive
+    /// number or `burst` is 0.
+    pub fn file<P: AsRef<Path>>(path: P, rate: f64, burst: u32) -> std::io::Result<Self> {
+        validate(rate, burst)?;
+        let path = path.as_ref().to_path_buf();
+        OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                rate,
+                burst: f64::from(burst),
+                store: Store::File(path)
+            })
+        })
+    }
+
+    /// Block until a request may be sent.
+    pub fn acquire(&self) -> std::io::Result<()> {
+        loop {
+            match self.try_acquire()? {
+                None => return Ok(()),
+                Some(wait) => thread::sleep(wait)
+            }
+        }
+    }
+
+    /// Take a token if one is available.
+    ///
+    /// Return how long to wait otherwise.
+    pub fn try_acquire(&self) -> std::io::Result<Option<Duration>> {
+        match self.inner.store {
+            Store::Memory(ref bucket) => {
+                let mut bucket = match bucket.lock() {
+                    Ok(bucket) => bucket,
+                    Err(poisoned) => poisoned.into_inner()
+                };
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last).as_secs_f64();
+                bucket.last = now;
+                let (tokens, wait) = self.take(bucket.tokens, elapsed);
+                bucket.tokens = tokens;
+                Ok(wait)
+            },
+            Store::File(ref path) => {
+                let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+                file.lock()?;
+                let result = self.take_from_file(&mut file);
+                file.unlock()?;
+                result
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    fn take_from_file(&self, file: &mut File) -> std::io::Result<Option<Duration>> {
+        let mut state = String::new();
+        file.read_to_string(&mut state)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let mut parts = state.split_whitespace().map(|i| i.parse::<f64>());
+        let (tokens, elapsed) = match (parts.next(), parts.next()) {
+            (Some(Ok(tokens)), Some(Ok(last))) => (tokens, (now - last).max(0.0)),
+            _ => (self.inner.burst, 0.0)
+        };
+        let (tokens, wait) = self.take(tokens, elapsed);
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{} {}", tokens, now)?;
+        Ok(wait)
+    }
+
+    #[doc(hidden)]
+    fn take(&self, tokens: f64, elapsed: f64) -> (f64, Option<Duration>) {
+        let tokens = (tokens + elapsed * self.inner.rate).min(self.inner.burst);
+        if tokens >= 1.0 {
+            (tokens - 1.0, None)
+        } else {
+            (tokens, Some(Duration::from_secs_f64((1.0 - tokens) / self.inner.rate)))
+        }
+    }
+}
+
+#[doc(hidden)]
+fn validate(rate: f64, burst: u32) -> std::io::Result<()> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Rate must be a positive number, got {}",rate)))
+    }
+    if burst == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "Burst must be at least 1"))
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use crate::StarByFaceError;
+
+/// Image looked up by StarByFace.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Image URI.
+    Url(String),
+    /// Image file.
+    File(PathBuf),
+    /// Image content and its file name.
+    Bytes {
+        data: Vec<u8>,
+        file_name: String
+    }
+}
+
+impl Source {
+
+    /// Return the kind of source: `url`, `file` or `bytes`.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Source::Url(_) => "url",
+            Source::File(_) => "file",
+            Source::Bytes { .. } => "bytes"
+        }
+    }
+
+    /// Return the SHA-256 of the image content in hex,
+    /// or of the path for files and of the URI for URLs.
+    pub fn hash(&self) -> String {
+        match *self {
+            Source::Url(ref image_uri) => sha256(image_uri.as_bytes()),
+            Source::File(ref image_file) => sha256(image_file.to_string_lossy().as_bytes()),
+            Source::Bytes { ref data, .. } => sha256(data)
+        }
+    }
+
+    /// Return the content and file name of a file or bytes, or
+    /// an error for URLs.
+    #[doc(hidden)]
+    pub(crate) fn read(&self) -> Result<(Vec<u8>, String), StarByFaceError> {
+        match *self {
+            Source::File(ref image_file) => match std::fs::read(image_file) {
+                Ok(data) => Ok((data, image_file.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "image".to_string()))),
+                Err(err) => Err(StarByFaceError::Error(err.to_string()))
+            },
+            Source::Bytes { ref data, ref file_name } => Ok((data.clone(), file_name.clone())),
+            Source::Url(_) => Err(StarByFaceError::Error(String::from("Only files and bytes can be read")))
+        }
+    }
+}
+
+/// Return the SHA-256 of `data` in hex.
+#[doc(hidden)]
+pub(crate) fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Return the file name with its extension replaced by `.jpg`.
+#[cfg(feature = "image")]
+#[doc(hidden)]
+pub(crate) fn jpeg_file_name(file_name: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, _)) => format!("{}.jpg",stem),
+        None => format!("{}.jpg",file_name)
+    }
+}
+
+#[doc(hidden)]
+pub(crate) fn mime_type(data: &[u8]) -> &'static str {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if data.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else if data.starts_with(b"BM") {
+        "image/bmp"
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        "image/tiff"
+    } else {
+        "application/octet-stream"
+    }
+}
@@ -0,0 +1,86 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::time::Duration;
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use crate::page::Page;
+use crate::StarByFaceError;
+
+#[doc(hidden)]
+const LOOKUPS: &str = "starbyface_lookups_total";
+
+#[doc(hidden)]
+const UPSTREAM_REQUESTS: &str = "starbyface_upstream_requests_total";
+
+#[doc(hidden)]
+const UPSTREAM_LATENCY: &str = "starbyface_upstream_latency_seconds";
+
+#[doc(hidden)]
+const RETRIES: &str = "starbyface_upstream_retries_total";
+
+#[doc(hidden)]
+const CACHE_HITS: &str = "starbyface_cache_hits_total";
+
+#[doc(hidden)]
+const CARDS: &str = "starbyface_parsed_cards";
+
+/// Describe the metrics recorded by lookups to the
+/// installed `metrics` recorder.
+///
+/// - `starbyface_lookups_total` by `source` and `outcome`
+///   (`ok` or the kind of `StarByFaceError`)
+/// - `starbyface_upstream_requests_total` by `source`
+/// - `starbyface_upstream_latency_seconds` by `source`
+/// - `starbyface_upstream_retries_total` by `source` and `reason`
+///   (`error` or the status retried)
+/// - `starbyface_cache_hits_total` by `cache`, lookups
+///   sharing an identical in-flight request
+/// - `starbyface_parsed_cards` by `source`
+pub fn describe_metrics() {
+    describe_counter!(LOOKUPS, "Lookups by source type and outcome");
+    describe_counter!(UPSTREAM_REQUESTS, "Requests sent to StarByFace by source type");
+    describe_histogram!(UPSTREAM_LATENCY, Unit::Seconds, "Latency of StarByFace requests by source type");
+    describe_counter!(RETRIES, "Requests sent again to StarByFace by source type and reason");
+    describe_counter!(CACHE_HITS, "Lookups answered without an upstream request");
+    describe_histogram!(CARDS, Unit::Count, "Celebrity cards parsed per response");
+}
+
+#[doc(hidden)]
+pub(crate) fn upstream(source: &'static str, latency: Duration) {
+    counter!(UPSTREAM_REQUESTS, "source" => source).increment(1);
+    histogram!(UPSTREAM_LATENCY, "source" => source).record(latency.as_secs_f64());
+}
+
+#[doc(hidden)]
+pub(crate) fn retry(source: &'static str, reason: &str) {
+    counter!(RETRIES, "source" => source, "reason" => reason.to_string()).increment(1);
+}
+
+#[doc(hidden)]
+pub(crate) fn lookup(source: &'static str, result: &Result<Page, StarByFaceError>, shared: bool) {
+    if shared {
+        counter!(CACHE_HITS, "cache" => "inflight").increment(1);
+    }
+    let outcome = match *result {
+        Ok(ref page) => {
+            if !shared {
+                histogram!(CARDS, "source" => source).record(page.diagnostics().cards as f64);
+            }
+            page.outcome()
+        },
+        Err(ref err) => err.kind()
+    };
+    counter!(LOOKUPS, "source" => source, "outcome" => outcome).increment(1);
+}
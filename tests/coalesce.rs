@@ -0,0 +1,93 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use starbyface::{Client, Lookup, Source};
+use common::{card, results, MockServer};
+
+const THREADS: usize = 6;
+
+fn page() -> String {
+    results(&[("female", &[card("Ann", "90")])])
+}
+
+/// Look up `source` from one thread per client at once.
+fn lookup_at_once(clients: Vec<Client>, source: Source) -> Vec<Lookup> {
+    let barrier = Arc::new(Barrier::new(clients.len()));
+    let handles: Vec<_> = clients.into_iter().map(|client| {
+        let (barrier, source) = (barrier.clone(), source.clone());
+        thread::spawn(move || {
+            barrier.wait();
+            Lookup::new(&source, &client)
+        })
+    }).collect();
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+}
+
+#[test]
+fn concurrent_lookups_share_one_request() {
+    let server = MockServer::with_delay(&page(), Duration::from_millis(300));
+    let client = Client::new().with_base_url(&server.base_url);
+    let source = Source::Url("https://example.com/face.jpg".to_string());
+    let lookups = lookup_at_once(vec![client; THREADS], source);
+    assert_eq!(server.hits(), 1);
+    for lookup in lookups {
+        assert_eq!(lookup.celebrities().unwrap()[0].name, "Ann");
+    }
+}
+
+#[test]
+fn concurrent_uploads_share_one_request() {
+    let server = MockServer::with_delay(&page(), Duration::from_millis(300));
+    let client = Client::new().with_base_url(&server.base_url).with_metadata_stripping(false);
+    let source = Source::Bytes { data: b"image".to_vec(), file_name: "face.jpg".to_string() };
+    let lookups = lookup_at_once(vec![client; THREADS], source);
+    assert_eq!(server.hits(), 1);
+    assert!(lookups.iter().all(|lookup| lookup.celebrities().is_ok()));
+}
+
+#[test]
+fn different_images_are_not_shared() {
+    let server = MockServer::with_delay(&page(), Duration::from_millis(100));
+    let client = Client::new().with_base_url(&server.base_url);
+    let barrier = Arc::new(Barrier::new(2));
+    let handles: Vec<_> = ["one", "two"].into_iter().map(|name| {
+        let (client, barrier) = (client.clone(), barrier.clone());
+        thread::spawn(move || {
+            barrier.wait();
+            Lookup::new(&Source::Url(format!("https://example.com/{}.jpg", name)), &client)
+        })
+    }).collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().celebrities().is_ok());
+    }
+    assert_eq!(server.hits(), 2);
+}
+
+#[test]
+fn reconfigured_clones_do_not_share_requests() {
+    let first = MockServer::with_delay(&page(), Duration::from_millis(300));
+    let second = MockServer::with_delay(&page(), Duration::from_millis(300));
+    let client = Client::new().with_base_url(&first.base_url);
+    let clients = vec![client.clone(), client.clone(), client.clone().with_base_url(&second.base_url), client.with_base_url(&second.base_url)];
+    let lookups = lookup_at_once(clients, Source::Url("https://example.com/face.jpg".to_string()));
+    assert_eq!(first.hits(), 1);
+    assert_eq!(second.hits(), 2);
+    assert!(lookups.iter().all(|lookup| lookup.celebrities().is_ok()));
+}
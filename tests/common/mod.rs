@@ -0,0 +1,125 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use starbyface::{Client, Lookup, Source};
+
+/// Upstream answering every request with the same page.
+pub struct MockServer {
+    pub base_url: String,
+    hits: Arc<AtomicUsize>
+}
+
+impl MockServer {
+
+    pub fn start(page: &str) -> Self {
+        Self::with_delay(page, Duration::ZERO)
+    }
+
+    /// `delay` Time waited before answering each request.
+    pub fn with_delay(page: &str, delay: Duration) -> Self {
+        Self::with_responses(vec![response(200, "", page)], delay)
+    }
+
+    /// `responses` Raw responses to the requests in order, the
+    /// last one answering every later request.
+    ///
+    /// `delay` Time waited before answering each request.
+    pub fn with_responses(responses: Vec<String>, delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break
+                };
+                let hit = counter.fetch_add(1, Ordering::SeqCst);
+                let response = responses[hit.min(responses.len() - 1)].clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream);
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                            break
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                length = value.trim().parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    let _ = reader.read_exact(&mut body);
+                    thread::sleep(delay);
+                    let _ = reader.get_mut().write_all(response.as_bytes());
+                });
+            }
+        });
+        Self { base_url, hits }
+    }
+
+    /// Return the number of requests received.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+/// Return a raw HTML response with extra `headers`, each ending
+/// with CRLF.
+pub fn response(status: u16, headers: &str, page: &str) -> String {
+    format!("HTTP/1.1 {} Status\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}", status, page.len(), headers, page)
+}
+
+/// Return a card of the results page.
+pub fn card(name: &str, similarity: &str) -> String {
+    format!(r#"<div class="card" similarity="{similarity}">
+            <div class="bar"><div class="fill"></div>
+            </div>
+            </div>
+            <a href="https://en.wikipedia.org/wiki/{name}" target="_blank">
+            <img class="photo" src="/img/{name} Photo.jpg" />
+            <p class="name">{name}</p></div>"#)
+}
+
+/// Return a results page with a section of cards per id.
+pub fn results(sections: &[(&str, &[String])]) -> String {
+    let mut page = String::from("<html><body>");
+    for (id, cards) in sections {
+        page.push_str(&format!(r#"<div id="{}-celebs-result">"#, id));
+        for card in cards.iter() {
+            page.push_str(card);
+        }
+        page.push_str("</div>");
+    }
+    page.push_str("</body></html>");
+    page
+}
+
+/// Look up an image URL through a mock server answering with `page`.
+pub fn lookup(page: &str, client: Client) -> Lookup {
+    let server = MockServer::start(page);
+    Lookup::new(&Source::Url("https://example.com/face.jpg".to_string()), &client.with_base_url(&server.base_url))
+}
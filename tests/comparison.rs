@@ -0,0 +1,34 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use std::time::Duration;
+use starbyface::{Client, Comparison, Source};
+use common::{card, response, results, MockServer};
+
+#[test]
+fn subject_without_results_shares_nothing() {
+    let found = results(&[("female", &[card("Ann", "90")])]);
+    let no_face = r#"<html><body><script>showError("No face found");</script></body></html>"#;
+    let server = MockServer::with_responses(vec![response(200, "", &found), response(200, "", no_face)], Duration::ZERO);
+    let client = Client::new().with_base_url(&server.base_url);
+    let a = Source::Url("https://example.com/a.jpg".to_string());
+    let b = Source::Url("https://example.com/b.jpg".to_string());
+    let comparison = Comparison::lookup(&a, &b, &client).unwrap();
+    assert!(comparison.shared.is_empty());
+    assert_eq!((comparison.overlap, comparison.score, comparison.rank_correlation), (0.0, 0.0, None));
+    assert_eq!(server.hits(), 2);
+}
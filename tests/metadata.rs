@@ -0,0 +1,369 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use starbyface::{strip_metadata, Client, Source, Lookup, StarByFaceError};
+
+const SECRETS: [&[u8]; 6] = [b"GPS-51.5007N", b"SERIAL-0042", b"2022:01:01 10:00:00", b"xmp-secret", b"iptc-secret", b"comment-secret"];
+
+fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// EXIF structure with an orientation tag and an ASCII tag.
+fn exif(orientation: u16) -> Vec<u8> {
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x02\0".to_vec();
+    exif.extend_from_slice(&[0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&orientation.to_le_bytes());
+    exif.extend_from_slice(&[0x00, 0x00]);
+    exif.extend_from_slice(&[0x31, 0xA4, 0x02, 0x00, 0x0C, 0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(b"SERIAL-0042\0GPS-51.5007N 2022:01:01 10:00:00");
+    exif
+}
+
+fn jpeg(orientation: u16) -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    jpeg.extend(segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+    jpeg.extend(segment(0xE1, &exif(orientation)));
+    jpeg.extend(segment(0xE1, b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta>xmp-secret</x:xmpmeta>"));
+    jpeg.extend(segment(0xE2, b"ICC_PROFILE\0\x01\x01profile"));
+    jpeg.extend(segment(0xED, b"Photoshop 3.0\08BIM\x04\x04iptc-secret"));
+    jpeg.extend(segment(0xFE, b"comment-secret"));
+    jpeg.extend(segment(0xDB, &[0; 65]));
+    jpeg.extend(segment(0xC0, &[8, 0, 1, 0, 1, 1, 1, 0x11, 0]));
+    jpeg.extend(segment(0xDA, &[1, 1, 0, 0, 63, 0]));
+    jpeg.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg.extend_from_slice(b"trailing GPS-51.5007N");
+    jpeg
+}
+
+fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&[0, 0, 0, 0]);
+    chunk
+}
+
+fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn assert_no_secrets(data: &[u8]) {
+    for secret in SECRETS {
+        assert!(!data.windows(secret.len()).any(|window| window == secret),
+                "{} survived", String::from_utf8_lossy(secret));
+    }
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test]
+fn strips_jpeg_metadata() {
+    let (stripped, removed) = strip_metadata(&jpeg(1)).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["EXIF", "XMP", "IPTC", "comment", "trailing data"]);
+    assert!(!contains(&stripped, b"Exif"));
+    assert!(contains(&stripped, b"JFIF"));
+    assert!(contains(&stripped, b"ICC_PROFILE"));
+    assert!(contains(&stripped, &[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD9]));
+    assert!(stripped.ends_with(&[0xFF, 0xD9]));
+}
+
+#[test]
+fn keeps_jpeg_orientation_only() {
+    let (stripped, _) = strip_metadata(&jpeg(6)).unwrap();
+    assert_no_secrets(&stripped);
+    let jfif = segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    assert!(stripped[2..].starts_with(&jfif));
+    let exif = &stripped[2 + jfif.len()..];
+    assert!(exif.starts_with(&[0xFF, 0xE1, 0x00, 0x22]));
+    assert_eq!(&exif[4..10], b"Exif\0\0");
+    assert_eq!(&exif[10..26], b"MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0");
+    assert_eq!(&exif[28..30], &[0x00, 0x06]);
+}
+
+#[test]
+fn strips_png_metadata() {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+    png.extend(png_chunk(b"eXIf", &exif(1)[6..]));
+    png.extend(png_chunk(b"tEXt", b"Comment\0comment-secret"));
+    png.extend(png_chunk(b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0xmp-secret"));
+    png.extend(png_chunk(b"tIME", &[7, 230, 1, 1, 10, 0, 0]));
+    png.extend(png_chunk(b"IDAT", b"pixels"));
+    png.extend(png_chunk(b"IEND", b""));
+    let (stripped, removed) = strip_metadata(&png).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["EXIF", "text", "XMP", "time"]);
+    let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+    expected.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+    expected.extend(png_chunk(b"IDAT", b"pixels"));
+    expected.extend(png_chunk(b"IEND", b""));
+    assert_eq!(stripped, expected);
+}
+
+#[test]
+fn strips_webp_metadata() {
+    let mut chunks = webp_chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    chunks.extend(webp_chunk(b"VP8 ", b"pixels"));
+    chunks.extend(webp_chunk(b"EXIF", &exif(1)[6..]));
+    chunks.extend(webp_chunk(b"XMP ", b"<x:xmpmeta>xmp-secret</x:xmpmeta>"));
+    let mut webp = b"RIFF".to_vec();
+    webp.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    webp.extend_from_slice(b"WEBP");
+    webp.extend(chunks);
+    let (stripped, removed) = strip_metadata(&webp).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["EXIF", "XMP"]);
+    let mut expected = b"RIFF".to_vec();
+    let mut chunks = webp_chunk(b"VP8X", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    chunks.extend(webp_chunk(b"VP8 ", b"pixels"));
+    expected.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    expected.extend_from_slice(b"WEBP");
+    expected.extend(chunks);
+    assert_eq!(stripped, expected);
+}
+
+/// EXIF TIFF structure written with the orientation only.
+const ORIENTATION_TIFF: &[u8] = b"MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+
+#[test]
+fn keeps_png_orientation_only() {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+    png.extend(png_chunk(b"eXIf", &exif(6)[6..]));
+    png.extend(png_chunk(b"IDAT", b"pixels"));
+    png.extend(png_chunk(b"IEND", b""));
+    let (stripped, removed) = strip_metadata(&png).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["EXIF"]);
+    let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+    expected.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+    expected.extend_from_slice(&[0, 0, 0, 26]);
+    expected.extend_from_slice(b"eXIf");
+    expected.extend_from_slice(ORIENTATION_TIFF);
+    expected.extend_from_slice(&0xD6674B69u32.to_be_bytes());
+    expected.extend(png_chunk(b"IDAT", b"pixels"));
+    expected.extend(png_chunk(b"IEND", b""));
+    assert_eq!(stripped, expected);
+}
+
+#[test]
+fn keeps_webp_orientation_only() {
+    let mut chunks = webp_chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    chunks.extend(webp_chunk(b"VP8 ", b"pixels"));
+    chunks.extend(webp_chunk(b"EXIF", &exif(6)));
+    chunks.extend(webp_chunk(b"XMP ", b"<x:xmpmeta>xmp-secret</x:xmpmeta>"));
+    let mut webp = b"RIFF".to_vec();
+    webp.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    webp.extend_from_slice(b"WEBP");
+    webp.extend(chunks);
+    let (stripped, removed) = strip_metadata(&webp).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["EXIF", "XMP"]);
+    let mut expected = b"RIFF".to_vec();
+    let mut chunks = webp_chunk(b"VP8X", &[0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    chunks.extend(webp_chunk(b"VP8 ", b"pixels"));
+    chunks.extend(webp_chunk(b"EXIF", ORIENTATION_TIFF));
+    expected.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+    expected.extend_from_slice(b"WEBP");
+    expected.extend(chunks);
+    assert_eq!(stripped, expected);
+}
+
+/// GIF extension block with one data sub-block.
+fn gif_extension(label: u8, data: &[u8]) -> Vec<u8> {
+    let mut extension = vec![0x21, label, data.len() as u8];
+    extension.extend_from_slice(data);
+    extension.push(0);
+    extension
+}
+
+#[test]
+fn strips_gif_metadata() {
+    let header = b"GIF89a\x01\0\x01\0\x80\0\0\0\0\0\xFF\xFF\xFF";
+    let netscape = [&[0x21, 0xFF, 0x0B][..], b"NETSCAPE2.0\x03\x01\0\0\0"].concat();
+    let xmp = [&[0x21, 0xFF, 0x0B][..], b"XMP DataXMP\x19<x:xmpmeta>xmp-secret</x>\0"].concat();
+    let control = gif_extension(0xF9, &[0, 0, 0, 0]);
+    let image = b"\x2C\0\0\0\0\x01\0\x01\0\0\x02\x02\x44\x01\0";
+    let mut gif = header.to_vec();
+    gif.extend_from_slice(&netscape);
+    gif.extend(gif_extension(0xFE, b"comment-secret"));
+    gif.extend_from_slice(&xmp);
+    gif.extend(gif_extension(0xFF, b"ICCRGBG1012"));
+    gif.extend_from_slice(&control);
+    gif.extend_from_slice(image);
+    gif.extend_from_slice(b"\x3Btrailing GPS-51.5007N");
+    let (stripped, removed) = strip_metadata(&gif).unwrap();
+    assert_no_secrets(&stripped);
+    assert_eq!(removed, ["comment", "XMP", "application data", "trailing data"]);
+    assert_eq!(stripped, [&header[..], &netscape, &control, image, b"\x3B"].concat());
+}
+
+#[test]
+fn passes_through_formats_it_cannot_strip() {
+    for data in [&b"II*\0\x08\0\0\0"[..],
+                 b"MM\0*\0\0\0\x08",
+                 b"\0\0\0\x18ftypheic\0\0\0\0",
+                 b"\0\0\0\x1CftypavifmiflMA1B"] {
+        assert_eq!(strip_metadata(data).unwrap(), (data.to_vec(), vec![]));
+    }
+    let bmp = b"BM\x1E\0\0\0\0\0\0\0\x1A\0\0\0";
+    assert_eq!(strip_metadata(bmp).unwrap(), (bmp.to_vec(), vec![]));
+}
+
+#[test]
+fn rejects_malformed_images() {
+    let mut jpeg = jpeg(1);
+    jpeg.truncate(30);
+    assert!(matches!(strip_metadata(&jpeg), Err(StarByFaceError::InvalidImage(_))));
+    assert!(matches!(strip_metadata(b"\x89PNG\r\n\x1a\n\0\0\0\x0DIHDR"), Err(StarByFaceError::InvalidImage(_))));
+    assert!(matches!(strip_metadata(b"GIF89a\x01\0\x01\0\0\0\0\x21\xFE\x05abc"), Err(StarByFaceError::InvalidImage(_))));
+}
+
+/// Look up `data` through a mock server and return the uploaded request.
+fn upload(client: Client, data: Vec<u8>) -> (Lookup, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                break
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let page = r#"<div id="female-celebs-result"><div class="card" similarity="90">
+            <div class="bar"><div class="fill"></div>
+            </div>
+            </div>
+            <a href="https://en.wikipedia.org/wiki/Ann" target="_blank">
+            <img class="photo" src="/img/Ann.jpg" />
+            <p class="name">Ann</p></div>"#;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", page.len(), page);
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        sender.send(body).unwrap();
+    });
+    let source = Source::Bytes { data, file_name: "face.jpg".to_string() };
+    let lookup = Lookup::new(&source, &client.with_base_url(&base_url));
+    (lookup, receiver.recv().unwrap())
+}
+
+#[test]
+fn uploads_without_metadata_by_default() {
+    let (lookup, body) = upload(Client::new(), jpeg(1));
+    assert_no_secrets(&body);
+    assert!(contains(&body, &[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD9]));
+    let preprocessing = lookup.preprocessing().unwrap();
+    assert_eq!(preprocessing.metadata_removed, ["EXIF", "XMP", "IPTC", "comment", "trailing data"]);
+    assert_eq!(lookup.celebrities().unwrap().len(), 1);
+}
+
+#[test]
+fn uploads_metadata_when_disabled() {
+    let (lookup, body) = upload(Client::new().with_metadata_stripping(false), jpeg(1));
+    assert!(contains(&body, b"SERIAL-0042"));
+    assert!(lookup.preprocessing().is_none());
+}
+
+#[test]
+fn uploads_tiff_images_unchanged() {
+    let tiff = b"II*\0\x08\0\0\0SERIAL-0042".to_vec();
+    let (lookup, body) = upload(Client::new(), tiff.clone());
+    assert!(contains(&body, &tiff));
+    assert!(lookup.preprocessing().unwrap().metadata_removed.is_empty());
+    assert_eq!(lookup.celebrities().unwrap().len(), 1);
+}
+
+#[cfg(feature = "image")]
+mod decoded {
+    use std::io::Cursor;
+    use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbImage};
+    use image::metadata::Orientation;
+    use starbyface::{strip_metadata, Client, Preprocessor};
+    use super::{exif, png_chunk, upload, webp_chunk};
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let mut data = vec![];
+        DynamicImage::ImageRgb8(RgbImage::new(4, 2)).write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    fn orientation(data: &[u8]) -> Orientation {
+        let mut decoder = ImageReader::new(Cursor::new(data)).with_guessed_format().unwrap().into_decoder().unwrap();
+        decoder.orientation().unwrap()
+    }
+
+    #[test]
+    fn decodes_the_png_orientation() {
+        let png = encode(ImageFormat::Png);
+        // The eXIf chunk has no valid CRC, so insert it after IHDR and let the stripped copy fix it.
+        let mut data = png[..33].to_vec();
+        data.extend(png_chunk(b"eXIf", &exif(6)[6..]));
+        data.extend_from_slice(&png[33..]);
+        let (stripped, _) = strip_metadata(&data).unwrap();
+        assert_eq!(orientation(&stripped), Orientation::Rotate90);
+    }
+
+    #[test]
+    fn decodes_the_webp_orientation() {
+        let webp = encode(ImageFormat::WebP);
+        let mut chunks = webp_chunk(b"VP8X", &[0x08, 0, 0, 0, 3, 0, 0, 1, 0, 0]);
+        chunks.extend_from_slice(&webp[12..]);
+        chunks.extend(webp_chunk(b"EXIF", &exif(6)[6..]));
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend(chunks);
+        let (stripped, _) = strip_metadata(&data).unwrap();
+        assert_eq!(orientation(&stripped), Orientation::Rotate90);
+    }
+
+    #[test]
+    fn uploads_converted_tiff_images() {
+        let client = Client::new().with_preprocessor(Preprocessor::new());
+        let (lookup, body) = upload(client, encode(ImageFormat::Tiff));
+        assert!(body.windows(3).any(|window| window == [0xFF, 0xD8, 0xFF]));
+        assert_eq!(lookup.preprocessing().unwrap().converted.as_deref(), Some("tiff"));
+        assert_eq!(lookup.celebrities().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use starbyface::{Client, Gender, StarByFaceError};
+use common::{card, lookup, results};
+
+const MARKUP_CHANGED: &str = r#"<html><body><div id="male-celebs-result">
+    <section class="celebrity" data-similarity="88"><h3>Bob</h3></section>
+    </div></body></html>"#;
+
+fn dump_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("starbyface-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn parses_results_page() {
+    let page = results(&[("male", &[card("Bob", "80%")]), ("female", &[card("Ann", "90%"), card("Eve", "70%")])]);
+    let lookup = lookup(&page, Client::new());
+    let celebrities = lookup.celebrities().unwrap();
+    let names: Vec<&str> = celebrities.iter().map(|celebrity| celebrity.name.as_str()).collect();
+    assert_eq!(names, ["Ann", "Eve", "Bob"]);
+    assert_eq!(celebrities[0].gender, Gender::FEMALE);
+    assert_eq!(celebrities[0].similarity, 90.0);
+    assert_eq!(celebrities[0].wiki, "https://en.wikipedia.org/wiki/Ann");
+    assert_eq!(celebrities[0].image, "/img/Ann%20Photo.jpg");
+    assert_eq!(celebrities[2].gender, Gender::MALE);
+    let diagnostics = lookup.diagnostics().unwrap();
+    assert!(diagnostics.male_section && diagnostics.female_section);
+    assert_eq!(diagnostics.sections, ["female-celebs-result", "male-celebs-result"]);
+    assert_eq!(diagnostics.similarity_attributes, 3);
+    assert_eq!(diagnostics.cards, 3);
+    assert_eq!(diagnostics.body_length, page.len());
+    assert!(diagnostics.is_results_page());
+    assert_eq!(lookup.raw_body(), Some(page.as_str()));
+    assert_eq!(lookup.highest_similarity_by_gender(Gender::MALE).unwrap(), lookup.lowest_similarity_by_gender(Gender::MALE).unwrap());
+}
+
+#[test]
+fn no_result_page_is_null() {
+    let page = r#"<html><body><script>showError("No face found");</script></body></html>"#;
+    let lookup = lookup(page, Client::new());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Null(_))));
+    assert_eq!(lookup.warning().as_deref(), Some("No face found"));
+    let diagnostics = lookup.diagnostics().unwrap();
+    assert!(!diagnostics.is_results_page());
+    assert_eq!(diagnostics.cards, 0);
+    assert!(diagnostics.dump.is_none());
+}
+
+#[test]
+fn empty_sections_are_null_with_a_warning() {
+    let page = r#"<div id="female-celebs-result"></div><script>showError("No celebrity found");</script>"#;
+    let lookup = lookup(page, Client::new());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Null(_))));
+    assert_eq!(lookup.warning().as_deref(), Some("No celebrity found"));
+}
+
+#[test]
+fn detects_markup_change() {
+    let lookup = lookup(MARKUP_CHANGED, Client::new());
+    match lookup.celebrities() {
+        Err(StarByFaceError::MarkupChanged(diagnostics)) => {
+            assert!(diagnostics.male_section);
+            assert!(!diagnostics.female_section);
+            assert_eq!(diagnostics.sections, ["male-celebs-result"]);
+            assert_eq!(diagnostics.cards, 0);
+            assert_eq!(diagnostics.body_length, MARKUP_CHANGED.len());
+            assert!(diagnostics.dump.is_none());
+        },
+        result => panic!("{:?}", result)
+    }
+    assert!(matches!(lookup.get_data_by_gender(Gender::MALE), Err(StarByFaceError::MarkupChanged(_))));
+}
+
+#[test]
+fn dumps_body_when_markup_changed() {
+    let dir = dump_dir("dump");
+    let lookup = lookup(MARKUP_CHANGED, Client::new().with_dump_dir(&dir));
+    let path = match lookup.celebrities() {
+        Err(StarByFaceError::MarkupChanged(diagnostics)) => diagnostics.dump.unwrap(),
+        result => panic!("{:?}", result)
+    };
+    assert!(path.starts_with(&dir));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), MARKUP_CHANGED);
+    assert_eq!(lookup.diagnostics().unwrap().dump.as_ref(), Some(&path));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn does_not_dump_parsed_pages() {
+    let dir = dump_dir("no-dump");
+    let page = results(&[("female", &[card("Ann", "90%")])]);
+    let lookup = lookup(&page, Client::new().with_dump_dir(&dir));
+    assert!(lookup.celebrities().is_ok());
+    assert!(lookup.diagnostics().unwrap().dump.is_none());
+    assert!(!dir.exists());
+}
+
+#[test]
+fn parses_reordered_attributes() {
+    let card = r#"<div similarity="85%" data-id="7" class="card">
+            <div class="bar"></div>
+            <a href="https://en.wikipedia.org/wiki/Ann" target="_blank"><img src="/img/Ann.jpg" class="photo" /></a>
+            <p class="name">Ann</p>
+        </div>"#.to_string();
+    let celebrities = lookup(&results(&[("female", &[card])]), Client::new()).celebrities().unwrap();
+    assert_eq!(celebrities.len(), 1);
+    assert_eq!(celebrities[0].similarity, 85.0);
+    assert_eq!(celebrities[0].name, "Ann");
+    assert_eq!(celebrities[0].image, "/img/Ann.jpg");
+    assert_eq!(celebrities[0].attributes["similarity"], "85%");
+    assert_eq!(celebrities[0].attributes["data-id"], "7");
+    assert_eq!(celebrities[0].attributes["class"], "card");
+}
+
+#[test]
+fn parses_attributes_split_across_lines() {
+    let card = r#"<div
+                class="card"
+                data-id="3"
+                similarity="72">
+            <a
+                href="https://en.wikipedia.org/wiki/Bob"><img
+                class="photo"
+                src="/img/Bob.jpg"></a>
+            <p
+                class="name">
+                Bob
+            </p>
+        </div>"#.to_string();
+    let celebrities = lookup(&results(&[("male", &[card])]), Client::new()).celebrities().unwrap();
+    assert_eq!(celebrities.len(), 1);
+    assert_eq!(celebrities[0].similarity, 72.0);
+    assert_eq!(celebrities[0].name, "Bob");
+    assert_eq!(celebrities[0].wiki, "https://en.wikipedia.org/wiki/Bob");
+    assert_eq!(celebrities[0].image, "/img/Bob.jpg");
+    assert_eq!(celebrities[0].attributes.len(), 3);
+    assert_eq!(celebrities[0].attributes["data-id"], "3");
+}
+
+#[test]
+fn parses_extra_links() {
+    let card = r#"<div class="card" similarity="64%">
+            <a href="/celebrity/ann">Profile</a>
+            <a href="https://en.wikipedia.org/wiki/Ann"><img src="/img/Ann.jpg" /></a>
+            <p class="name">Ann</p>
+            <a href="https://www.imdb.com/find?q=Ann">IMDb</a>
+            <a href="https://en.wikipedia.org/wiki/Ann">Wikipedia</a>
+        </div>"#.to_string();
+    let celebrities = lookup(&results(&[("female", &[card])]), Client::new()).celebrities().unwrap();
+    assert_eq!(celebrities[0].wiki, "https://en.wikipedia.org/wiki/Ann");
+    assert_eq!(celebrities[0].links, ["/celebrity/ann", "https://www.imdb.com/find?q=Ann"]);
+    assert_eq!(celebrities[0].name, "Ann");
+}
+
+#[test]
+fn ranks_cards_within_their_section() {
+    let page = results(&[
+        ("male", &[card("Bob", "80%"), card("Dan", "60%")]),
+        ("female", &[card("Ann", "90%")]),
+        ("male", &[card("Sam", "50%")])
+    ]);
+    let celebrities = lookup(&page, Client::new()).celebrities().unwrap();
+    let ranks: Vec<(&str, usize)> = celebrities.iter().map(|celebrity| (celebrity.name.as_str(), celebrity.rank)).collect();
+    assert_eq!(ranks, [("Ann", 1), ("Bob", 1), ("Dan", 2), ("Sam", 3)]);
+}
+
+#[test]
+fn serializes_attributes_and_links() {
+    let card = r#"<div data-id="9" similarity="77%" class="card">
+            <a href="https://en.wikipedia.org/wiki/Ann"><img src="/img/Ann.jpg" /></a>
+            <a href="https://www.imdb.com/find?q=Ann">IMDb</a>
+            <p>Ann</p>
+        </div>"#.to_string();
+    let lookup = lookup(&results(&[("female", &[card])]), Client::new());
+    let json = json::parse(&lookup.get_data().unwrap()[0]).unwrap();
+    assert_eq!(json["rank"], 1);
+    assert_eq!(json["similarity"], "77%");
+    assert_eq!(json["attributes"]["data-id"], "9");
+    assert_eq!(json["attributes"]["class"], "card");
+    assert_eq!(json["links"][0], "https://www.imdb.com/find?q=Ann");
+    assert_eq!(json["links"].len(), 1);
+}
+
+#[test]
+fn discovers_other_sections() {
+    let page = results(&[
+        ("kids", &[card("Kim", "95%")]),
+        ("male", &[card("Bob", "80%")]),
+        ("female", &[card("Ann", "90%")])
+    ]);
+    let lookup = lookup(&page, Client::new());
+    let celebrities = lookup.celebrities().unwrap();
+    let genders: Vec<Gender> = celebrities.iter().map(|celebrity| celebrity.gender.clone()).collect();
+    assert_eq!(genders, [Gender::FEMALE, Gender::MALE, Gender::Other("kids".to_string())]);
+    assert_eq!(celebrities[2].name, "Kim");
+    assert_eq!(celebrities[2].rank, 1);
+    assert_eq!(lookup.diagnostics().unwrap().sections, ["female-celebs-result", "male-celebs-result", "kids-celebs-result"]);
+    let kids = lookup.get_data_by_gender(Gender::Other("kids".to_string())).unwrap();
+    assert_eq!(json::parse(&kids[0]).unwrap()["gender"], "kids");
+    assert_eq!(lookup.highest_similarity().unwrap().len(), 3);
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg(feature = "image")]
+
+use std::io::Cursor;
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbImage};
+use starbyface::{Preprocessor, StarByFaceError};
+
+/// Return a `width` x `height` image, red on the left half and
+/// blue on the right one.
+fn image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| if x < width / 2 {
+        image::Rgb([255, 0, 0])
+    } else {
+        image::Rgb([0, 0, 255])
+    }))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    let mut data = vec![];
+    image.write_to(&mut Cursor::new(&mut data), format).unwrap();
+    data
+}
+
+/// Return a JPEG of the image with an EXIF orientation.
+fn oriented_jpeg(image: &DynamicImage, orientation: u16) -> Vec<u8> {
+    let jpeg = encode(image, ImageFormat::Jpeg);
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+    exif.extend_from_slice(&orientation.to_le_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    let mut data = jpeg[..2].to_vec();
+    data.extend_from_slice(&[0xFF, 0xE1]);
+    data.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+    data.extend_from_slice(&exif);
+    data.extend_from_slice(&jpeg[2..]);
+    data
+}
+
+fn decode(data: &[u8]) -> DynamicImage {
+    assert_eq!(image::guess_format(data).unwrap(), ImageFormat::Jpeg);
+    image::load_from_memory(data).unwrap()
+}
+
+#[test]
+fn applies_the_orientation() {
+    let (data, preprocessing) = Preprocessor::new().process(&oriented_jpeg(&image(40, 20), 6)).unwrap();
+    assert_eq!(preprocessing.orientation, Some(6));
+    let rotated = decode(&data);
+    assert_eq!(rotated.dimensions(), (20, 40));
+    // Rotated clockwise, the red left half is now on top.
+    assert!(rotated.get_pixel(10, 5)[0] > 200);
+    assert!(rotated.get_pixel(10, 35)[2] > 200);
+}
+
+#[test]
+fn keeps_the_orientation_when_disabled() {
+    let data = oriented_jpeg(&image(40, 20), 6);
+    let (processed, preprocessing) = Preprocessor::new().with_orientation(false).process(&data).unwrap();
+    assert_eq!(processed, data);
+    assert_eq!(preprocessing.orientation, None);
+}
+
+#[test]
+fn downscales_large_images() {
+    let data = encode(&image(300, 100), ImageFormat::Jpeg);
+    let (processed, preprocessing) = Preprocessor::new().with_max_dimension(Some(150)).process(&data).unwrap();
+    assert_eq!(preprocessing.resized, Some(((300, 100), (150, 50))));
+    assert_eq!(preprocessing.converted, None);
+    assert_eq!(decode(&processed).dimensions(), (150, 50));
+}
+
+#[test]
+fn keeps_small_jpeg_images() {
+    let data = encode(&image(300, 100), ImageFormat::Jpeg);
+    let (processed, preprocessing) = Preprocessor::new().process(&data).unwrap();
+    assert_eq!(processed, data);
+    assert_eq!(preprocessing.resized, None);
+    assert_eq!(preprocessing.format.as_deref(), Some("jpeg"));
+}
+
+#[test]
+fn converts_to_jpeg() {
+    for (format, name) in [(ImageFormat::Png, "png"), (ImageFormat::Bmp, "bmp"), (ImageFormat::Tiff, "tiff"), (ImageFormat::WebP, "webp")] {
+        let data = encode(&image(60, 40), format);
+        let (processed, preprocessing) = Preprocessor::new().process(&data).unwrap();
+        assert_eq!(preprocessing.converted.as_deref(), Some(name));
+        assert_eq!(preprocessing.length, processed.len());
+        let converted = decode(&processed);
+        assert_eq!(converted.dimensions(), (60, 40));
+        assert!(converted.get_pixel(5, 20)[0] > 200);
+    }
+}
+
+#[test]
+fn keeps_the_format_when_conversion_is_disabled() {
+    let data = encode(&image(60, 40), ImageFormat::Png);
+    let (processed, preprocessing) = Preprocessor::new().with_conversion(false).process(&data).unwrap();
+    assert_eq!(processed, data);
+    assert_eq!(preprocessing.converted, None);
+}
+
+#[test]
+fn keeps_unknown_formats() {
+    let (processed, preprocessing) = Preprocessor::new().process(b"not an image").unwrap();
+    assert_eq!(processed, b"not an image");
+    assert_eq!(preprocessing.format, None);
+}
+
+#[test]
+fn rejects_corrupt_images() {
+    let mut data = encode(&image(60, 40), ImageFormat::Png);
+    data.truncate(40);
+    assert!(matches!(Preprocessor::new().process(&data), Err(StarByFaceError::InvalidImage(_))));
+}
@@ -0,0 +1,100 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+#![cfg(feature = "image")]
+
+use std::io::Cursor;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use starbyface::{Client, Lookup, QualityAnalyzer, QualityIssue, Source, StarByFaceError};
+
+/// Return a `size` checkerboard of 8 pixel squares alternating
+/// between the `dark` and `light` levels.
+fn checkerboard(size: u32, dark: u8, light: u8) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(size, size, |x, y| if (x / 8 + y / 8) % 2 == 0 {
+        Luma([dark])
+    } else {
+        Luma([light])
+    }))
+}
+
+fn png(image: &DynamicImage) -> Vec<u8> {
+    let mut data = vec![];
+    image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+    data
+}
+
+#[test]
+fn accepts_sharp_images() {
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(400, 60, 190))).unwrap();
+    assert_eq!((quality.width, quality.height), (400, 400));
+    assert!(quality.sharpness > 1000.0, "{}", quality);
+    assert!((quality.brightness - 0.49).abs() < 0.01, "{}", quality);
+    assert_eq!(quality.face_ratio, None);
+    assert!(quality.issues.is_empty(), "{}", quality);
+}
+
+#[test]
+fn reports_blurred_images() {
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(400, 60, 190).blur(6.0))).unwrap();
+    assert!(quality.sharpness < 20.0, "{}", quality);
+    assert!(matches!(quality.issues[..], [QualityIssue::Blurry(sharpness)] if sharpness == quality.sharpness));
+    let quality = QualityAnalyzer::new().with_min_sharpness(0.0).analyze(&png(&checkerboard(400, 60, 190).blur(6.0))).unwrap();
+    assert!(quality.issues.is_empty(), "{}", quality);
+}
+
+#[test]
+fn reports_uniform_images_as_blurry() {
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(400, 128, 128))).unwrap();
+    assert_eq!(quality.sharpness, 0.0);
+    assert_eq!(quality.issues, [QualityIssue::Blurry(0.0)]);
+}
+
+#[test]
+fn reports_exposure() {
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(400, 10, 40))).unwrap();
+    assert!(matches!(quality.issues[..], [QualityIssue::Underexposed(brightness)] if brightness < 0.15), "{}", quality);
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(400, 225, 255))).unwrap();
+    assert!(matches!(quality.issues[..], [QualityIssue::Overexposed(brightness)] if brightness > 0.9), "{}", quality);
+    let quality = QualityAnalyzer::new().with_brightness_range(0.0, 1.0).analyze(&png(&checkerboard(400, 10, 40))).unwrap();
+    assert!(quality.issues.is_empty(), "{}", quality);
+}
+
+#[test]
+fn reports_small_images() {
+    let quality = QualityAnalyzer::new().analyze(&png(&checkerboard(100, 60, 190))).unwrap();
+    assert_eq!(quality.issues, [QualityIssue::LowResolution { width: 100, height: 100 }]);
+    let quality = QualityAnalyzer::new().with_min_resolution(100).analyze(&png(&checkerboard(100, 60, 190))).unwrap();
+    assert!(quality.issues.is_empty(), "{}", quality);
+}
+
+#[test]
+fn scores_large_images_downscaled() {
+    let small = QualityAnalyzer::new().analyze(&png(&checkerboard(512, 60, 190).blur(2.0))).unwrap();
+    let large = QualityAnalyzer::new().analyze(&png(&checkerboard(512, 60, 190).blur(2.0).resize(2048, 2048, image::imageops::FilterType::Triangle))).unwrap();
+    assert_eq!((large.width, large.height), (2048, 2048));
+    assert!((large.sharpness - small.sharpness).abs() / small.sharpness < 0.25, "{} {}", small, large);
+}
+
+#[test]
+fn fails_low_quality_images_when_enforced() {
+    let client = Client::new()
+        .with_base_url("http://127.0.0.1:1")
+        .with_quality_analyzer(QualityAnalyzer::new().with_enforcement(true));
+    let source = Source::Bytes { data: png(&checkerboard(100, 10, 40)), file_name: "face.png".to_string() };
+    match Lookup::new(&source, &client).celebrities() {
+        Err(StarByFaceError::LowQuality(issues)) => assert_eq!(issues.len(), 2, "{:?}", issues),
+        result => panic!("{:?}", result)
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use starbyface::RateLimiter;
+
+fn state_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("starbyface-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn burst_is_available_at_once() {
+    let limiter = RateLimiter::new(1.0, 3).unwrap();
+    for _ in 0..3 {
+        assert_eq!(limiter.try_acquire().unwrap(), None);
+    }
+    assert!(limiter.try_acquire().unwrap().is_some());
+}
+
+#[test]
+fn wait_matches_the_missing_token() {
+    let limiter = RateLimiter::new(2.0, 1).unwrap();
+    assert_eq!(limiter.try_acquire().unwrap(), None);
+    let wait = limiter.try_acquire().unwrap().unwrap();
+    assert!(wait <= Duration::from_millis(500), "{:?}", wait);
+    assert!(wait >= Duration::from_millis(400), "{:?}", wait);
+}
+
+#[test]
+fn tokens_refill_over_time() {
+    let limiter = RateLimiter::new(20.0, 1).unwrap();
+    assert_eq!(limiter.try_acquire().unwrap(), None);
+    assert!(limiter.try_acquire().unwrap().is_some());
+    thread::sleep(Duration::from_millis(80));
+    assert_eq!(limiter.try_acquire().unwrap(), None);
+}
+
+#[test]
+fn clones_share_the_bucket() {
+    let limiter = RateLimiter::new(0.1, 1).unwrap();
+    let clone = limiter.clone();
+    assert_eq!(limiter.try_acquire().unwrap(), None);
+    assert!(clone.try_acquire().unwrap().is_some());
+}
+
+#[test]
+fn file_limiters_share_the_bucket() {
+    let path = state_file("shared");
+    let first = RateLimiter::file(&path, 0.1, 2).unwrap();
+    let second = RateLimiter::file(&path, 0.1, 2).unwrap();
+    assert_eq!(first.try_acquire().unwrap(), None);
+    assert_eq!(second.try_acquire().unwrap(), None);
+    let wait = first.try_acquire().unwrap().unwrap();
+    assert!(wait > Duration::from_secs(9), "{:?}", wait);
+    assert!(second.try_acquire().unwrap().is_some());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn file_state_is_reset_when_unreadable() {
+    let path = state_file("garbage");
+    std::fs::write(&path, "not a bucket").unwrap();
+    let limiter = RateLimiter::file(&path, 0.1, 1).unwrap();
+    assert_eq!(limiter.try_acquire().unwrap(), None);
+    assert!(limiter.try_acquire().unwrap().is_some());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn invalid_rate_and_burst_are_rejected() {
+    for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert_eq!(RateLimiter::new(rate, 1).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", rate);
+        assert_eq!(RateLimiter::file(state_file("invalid"), rate, 1).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", rate);
+    }
+    assert_eq!(RateLimiter::new(1.0, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(RateLimiter::file(state_file("invalid"), 1.0, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
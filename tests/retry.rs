@@ -0,0 +1,102 @@
+/*
+ * Copyright 2022 XXIV
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+mod common;
+
+use std::time::Duration;
+use starbyface::{Client, Lookup, Source, StarByFaceError};
+use common::{card, response, results, MockServer};
+
+fn page() -> String {
+    results(&[("female", &[card("Ann", "90")])])
+}
+
+/// Look up through a mock server answering with `responses`.
+fn look_up(responses: Vec<String>, client: Client, source: &Source) -> (Lookup, MockServer) {
+    let server = MockServer::with_responses(responses, Duration::ZERO);
+    let lookup = Lookup::new(source, &client.with_base_url(&server.base_url));
+    (lookup, server)
+}
+
+fn url() -> Source {
+    Source::Url("https://example.com/face.jpg".to_string())
+}
+
+#[test]
+fn retries_unavailable_upstream() {
+    let responses = vec![response(503, "", "busy"), response(502, "", "bad gateway"), response(200, "", &page())];
+    let (lookup, server) = look_up(responses, Client::new().with_retries(2), &url());
+    assert_eq!(lookup.celebrities().unwrap().len(), 1);
+    assert_eq!(server.hits(), 3);
+}
+
+#[test]
+fn gives_up_after_the_last_retry() {
+    let (lookup, server) = look_up(vec![response(503, "", "busy")], Client::new().with_retries(1), &url());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Status { status: 503, .. })));
+    assert_eq!(server.hits(), 2);
+}
+
+#[test]
+fn does_not_retry_by_default() {
+    let responses = vec![response(503, "", "busy"), response(200, "", &page())];
+    let (lookup, server) = look_up(responses, Client::new(), &url());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Status { status: 503, .. })));
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn does_not_retry_client_errors_and_challenges() {
+    let (lookup, server) = look_up(vec![response(404, "", "missing")], Client::new().with_retries(2), &url());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Status { status: 404, .. })));
+    assert_eq!(server.hits(), 1);
+    let challenge = response(503, "cf-mitigated: challenge\r\n", "Just a moment...");
+    let (lookup, server) = look_up(vec![challenge], Client::new().with_retries(2), &url());
+    assert!(matches!(lookup.celebrities(), Err(StarByFaceError::Challenge { status: 503, .. })));
+    assert_eq!(server.hits(), 1);
+}
+
+#[test]
+fn honours_retry_after() {
+    let responses = vec![response(429, "Retry-After: 1\r\n", "slow down"), response(200, "", &page())];
+    let start = std::time::Instant::now();
+    let (lookup, server) = look_up(responses, Client::new().with_retries(1), &url());
+    assert_eq!(lookup.celebrities().unwrap().len(), 1);
+    assert_eq!(server.hits(), 2);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[test]
+fn resends_uploaded_bytes() {
+    let source = Source::Bytes { data: vec![0xFF, 0xD8, 0xFF, 0xD9], file_name: "face.jpg".to_string() };
+    let responses = vec![response(500, "", "error"), response(200, "", &page())];
+    let (lookup, server) = look_up(responses, Client::new().with_retries(1), &source);
+    assert_eq!(lookup.celebrities().unwrap().len(), 1);
+    assert_eq!(server.hits(), 2);
+}
+
+#[test]
+fn retries_connection_errors() {
+    let client = Client::new().with_retries(1).with_base_url("http://127.0.0.1:1");
+    let start = std::time::Instant::now();
+    match Lookup::new(&url(), &client).celebrities() {
+        Err(StarByFaceError::Error(message)) => {
+            assert!(message.starts_with("error sending request"), "{}", message);
+            assert!(!message.contains("127.0.0.1"), "{}", message);
+        },
+        result => panic!("{:?}", result)
+    }
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}